@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+/// Settings of adaptive sampling, which stops sampling a pixel once its noise
+/// is low enough instead of always taking `samples_per_pixel`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveSampling {
+    /// Samples taken before the noise is first estimated, and between two estimates
+    pub min_samples: u32,
+    pub max_samples: u32,
+    /// Standard error of the mean at which a pixel is converged, relative to
+    /// the square root of its luminance, which is about twice the noise
+    /// left after gamma correction
+    pub threshold: f64,
+    /// PNG file receiving the number of samples of each pixel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heatmap: Option<String>,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            min_samples: 16,
+            max_samples: 1024,
+            threshold: 0.01,
+            heatmap: None,
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    /// Whether a pixel with the given statistics can stop sampling, checked
+    /// after every batch of `min_samples`.
+    pub fn converged(&self, stats: &PixelStats) -> bool {
+        let min_samples = self.min_samples.max(2);
+        if stats.count() < min_samples || !stats.count().is_multiple_of(min_samples) {
+            return false;
+        }
+        // Gamma correction magnifies the noise of dark pixels, so they get a smaller tolerance
+        stats.standard_error() <= self.threshold * stats.mean().max(1e-4).sqrt()
+    }
+}
+
+/// Running mean and variance of the luminance of a pixel's samples (Welford).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelStats {
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Count, mean and sum of squared deviations, which `from_raw` restores.
+    pub fn raw(&self) -> [f64; 3] {
+        [self.count as f64, self.mean, self.m2]
+    }
+
+    pub fn from_raw([count, mean, m2]: [f64; 3]) -> Self {
+        Self {
+            count: count as u32,
+            mean,
+            m2,
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Unbiased variance of the samples.
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    /// Estimated standard deviation of the mean.
+    pub fn standard_error(&self) -> f64 {
+        (self.variance() / self.count as f64).sqrt()
+    }
+}
+
+/// Color of a heatmap cell, from black for no samples through red and yellow
+/// to white for `max`.
+pub fn heatmap_color(count: u32, max: u32) -> [u8; 3] {
+    let t = count as f64 / max.max(1) as f64;
+    let channel = |offset: f64| ((3.0 * t - offset).clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(0.0), channel(1.0), channel(2.0)]
+}
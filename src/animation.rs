@@ -0,0 +1,316 @@
+use std::ops::RangeInclusive;
+
+use palette::Srgb;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    light::LightSource, material::Material, output::numbered_path, renderer::render, state::State,
+    vec3::Vec3,
+};
+
+/// Keyframed changes of the scene over the `frames` of the state.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Animation {
+    pub tracks: Vec<Track>,
+}
+
+/// Values a parameter of the scene takes at some frames, interpolated in
+/// between and held before the first and after the last.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub target: Target,
+    /// Sorted by frame
+    pub keys: Vec<Keyframe>,
+}
+
+/// Animated parameter. Spheres are given by their index in `entities_vec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Target {
+    LookFrom,
+    LookAt,
+    Vup,
+    /// Vertical field of view, in degrees
+    Vfov,
+    Aperture,
+    FocusDist,
+    /// Center of a sphere, which is blurred along its way to the next frame
+    /// when the shutter is open
+    Center(usize),
+    Radius(usize),
+    /// Albedo of a Lambertian or metal sphere
+    Albedo(usize),
+    /// Fuzz of a metal sphere
+    Fuzz(usize),
+    /// Index of refraction of a dielectric sphere
+    RefractionIndex(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Frame number, from 1 like the images, which may fall between two
+    /// frames
+    pub frame: f64,
+    pub value: KeyValue,
+    /// Interpolation up to the next key
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+/// Number, or point, vector or color as three numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyValue {
+    Number(f64),
+    Triple([f64; 3]),
+}
+
+impl KeyValue {
+    fn components(&self) -> [f64; 3] {
+        match *self {
+            KeyValue::Number(value) => [value, 0.0, 0.0],
+            KeyValue::Triple(values) => values,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// Straight to the next key at constant speed.
+    #[default]
+    Linear,
+    /// Along a cubic Bézier curve whose handles follow the neighbouring keys,
+    /// so that the motion is smooth through the key and eases in and out of
+    /// the first and last keys.
+    Bezier,
+}
+
+impl Target {
+    /// Whether the values are triples rather than numbers.
+    fn is_triple(&self) -> bool {
+        matches!(
+            self,
+            Target::LookFrom | Target::LookAt | Target::Vup | Target::Center(_) | Target::Albedo(_)
+        )
+    }
+
+    fn sphere(&self) -> Option<usize> {
+        match *self {
+            Target::Center(i)
+            | Target::Radius(i)
+            | Target::Albedo(i)
+            | Target::Fuzz(i)
+            | Target::RefractionIndex(i) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+impl Track {
+    /// Value of the parameter at `frame`, as three numbers the first of which
+    /// holds a number.
+    pub fn value_at(&self, frame: f64) -> [f64; 3] {
+        let keys = &self.keys;
+        let next = keys.partition_point(|key| key.frame <= frame);
+        if next == 0 {
+            return keys[0].value.components();
+        }
+        if next == keys.len() {
+            return keys[next - 1].value.components();
+        }
+
+        let (start, end) = (&keys[next - 1], &keys[next]);
+        let span = end.frame - start.frame;
+        let s = (frame - start.frame) / span;
+        let (p0, p3) = (start.value.components(), end.value.components());
+        match start.interpolation {
+            Interpolation::Linear => std::array::from_fn(|c| p0[c] + s * (p3[c] - p0[c])),
+            Interpolation::Bezier => {
+                // Handles a third of the way along the tangents, which keeps
+                // the curve at constant speed in time
+                let (m0, m1) = (self.tangent(next - 1), self.tangent(next));
+                std::array::from_fn(|c| {
+                    let p1 = p0[c] + m0[c] * span / 3.0;
+                    let p2 = p3[c] - m1[c] * span / 3.0;
+                    let t = 1.0 - s;
+                    t * t * t * p0[c]
+                        + 3.0 * t * t * s * p1
+                        + 3.0 * t * s * s * p2
+                        + s * s * s * p3[c]
+                })
+            }
+        }
+    }
+
+    /// Change per frame of the curve through key `i`, flat on the first and
+    /// last keys.
+    fn tangent(&self, i: usize) -> [f64; 3] {
+        if i == 0 || i + 1 == self.keys.len() {
+            return [0.0; 3];
+        }
+        let (before, after) = (&self.keys[i - 1], &self.keys[i + 1]);
+        let (a, b) = (before.value.components(), after.value.components());
+        std::array::from_fn(|c| (b[c] - a[c]) / (after.frame - before.frame))
+    }
+}
+
+impl Animation {
+    /// Checks that every track has sorted keys of the right kind for a
+    /// parameter of the scene.
+    pub fn check(&self, state: &State) -> Result<(), String> {
+        for track in &self.tracks {
+            let target = track.target;
+            if track.keys.is_empty() {
+                return Err(format!("the track of {target:?} has no keys"));
+            }
+            if track.keys.windows(2).any(|k| k[0].frame >= k[1].frame) {
+                return Err(format!("the keys of {target:?} are not sorted by frame"));
+            }
+            let triples = track
+                .keys
+                .iter()
+                .all(|key| matches!(key.value, KeyValue::Triple(_)));
+            let numbers = track
+                .keys
+                .iter()
+                .all(|key| matches!(key.value, KeyValue::Number(_)));
+            if (target.is_triple() && !triples) || (!target.is_triple() && !numbers) {
+                let kind = if target.is_triple() {
+                    "three numbers"
+                } else {
+                    "a number"
+                };
+                return Err(format!("the keys of {target:?} must be {kind}"));
+            }
+
+            if target == Target::Aperture && state.camera.exposure().is_some() {
+                return Err("the aperture follows the f_stop of the exposure".to_string());
+            }
+            if let Some(i) = target.sphere() {
+                let sphere = state
+                    .entities_vec
+                    .get(i)
+                    .ok_or_else(|| format!("{target:?} animates a missing sphere"))?;
+                let material = sphere.material;
+                let fits = match target {
+                    Target::Albedo(_) => {
+                        matches!(material, Material::Lambertian(_) | Material::Metal(_))
+                    }
+                    Target::Fuzz(_) => matches!(material, Material::Metal(_)),
+                    Target::RefractionIndex(_) => matches!(material, Material::Dielectric(_)),
+                    _ => true,
+                };
+                if !fits {
+                    return Err(format!("{target:?} does not apply to {material:?}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the scene of `state` to `frame`, leaving the parameters without
+    /// a track as they are.
+    pub fn apply(&self, state: &mut State, frame: u32) {
+        let frame = frame as f64;
+        let before = state.entities_vec.clone();
+        let (open, close) = state.camera.shutter();
+        let mut view = state.camera.view();
+        let mut camera_moved = false;
+
+        for track in &self.tracks {
+            let value = track.value_at(frame);
+            let vector = Vec3::new(value[0], value[1], value[2]);
+            camera_moved |= track.target.sphere().is_none();
+            match track.target {
+                Target::LookFrom => view.look_from = vector,
+                Target::LookAt => view.look_at = vector,
+                Target::Vup => view.vup = vector,
+                Target::Vfov => view.vfov = value[0],
+                Target::Aperture => view.aperture = value[0],
+                Target::FocusDist => view.focus_dist = Some(value[0]),
+                Target::Center(i) => {
+                    let sphere = &mut state.entities_vec[i];
+                    sphere.center = vector;
+                    // Times of the rays are measured in frames
+                    sphere.center_end = (close > open).then(|| {
+                        let next = track.value_at(frame + 1.0);
+                        Vec3::new(next[0], next[1], next[2])
+                    });
+                }
+                Target::Radius(i) => state.entities_vec[i].radius = value[0],
+                Target::Albedo(i) => {
+                    let albedo = Srgb::new(value[0] as f32, value[1] as f32, value[2] as f32);
+                    match &mut state.entities_vec[i].material {
+                        Material::Lambertian(lambertian) => lambertian.albedo = albedo,
+                        Material::Metal(metal) => metal.albedo = albedo,
+                        _ => {}
+                    }
+                }
+                Target::Fuzz(i) => {
+                    if let Material::Metal(metal) = &mut state.entities_vec[i].material {
+                        metal.fuzz = value[0];
+                    }
+                }
+                Target::RefractionIndex(i) => {
+                    if let Material::Dielectric(dielectric) = &mut state.entities_vec[i].material {
+                        dielectric.ir = value[0];
+                    }
+                }
+            }
+        }
+        if camera_moved {
+            state.camera.set_view(view);
+        }
+
+        // The lights registered for emissive spheres follow them
+        let lights = state.lights.get_or_insert_with(Vec::new);
+        for light in lights.iter_mut() {
+            let LightSource::Sphere(s) = light else {
+                continue;
+            };
+            let registered = before.iter().zip(&state.entities_vec).find(|(old, _)| {
+                old.material.emitted().is_some()
+                    && (s.center - old.center).near_zero()
+                    && s.radius == old.radius
+            });
+            if let Some((_, new)) = registered {
+                *s = *new;
+            }
+        }
+        // The material IDs stay those of the first frame, which keeps the ID
+        // pass steady while materials change
+        state.build_light_selector();
+    }
+}
+
+/// Renders the `frames` of the state, numbering the images, or the single
+/// image of a still.
+pub fn render_frames(state: &mut State, frames: RangeInclusive<u32>) {
+    let animation = state.animation.clone().unwrap_or_default();
+    if state.frames <= 1 {
+        animation.apply(state, 1);
+        render(state);
+        return;
+    }
+
+    let output = state.output.path.clone();
+    let hdr_output = state.hdr_output.clone();
+    let checkpoint = state
+        .progressive
+        .as_ref()
+        .and_then(|p| p.checkpoint.clone());
+    let heatmap = state.adaptive.as_ref().and_then(|a| a.heatmap.clone());
+    for frame in frames {
+        println!("Frame {frame}/{}", state.frames);
+        animation.apply(state, frame);
+        state.output.path = numbered_path(&output, frame);
+        state.hdr_output = hdr_output.as_deref().map(|path| numbered_path(path, frame));
+        if let Some(progressive) = &mut state.progressive {
+            progressive.checkpoint = checkpoint.as_deref().map(|path| numbered_path(path, frame));
+        }
+        if let Some(adaptive) = &mut state.adaptive {
+            adaptive.heatmap = heatmap.as_deref().map(|path| numbered_path(path, frame));
+        }
+        render(state);
+    }
+}
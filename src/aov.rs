@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    film::Film,
+    filter::Filter,
+    hittable::{sphere::Sphere, HitRecord},
+    material::Material,
+    output::{sibling_path, write_exr_channels, write_float_image, Output, OutputFormat},
+    ray::Ray,
+    tile::Tile,
+    vec3::Color,
+};
+
+/// Arbitrary output variable: a pass rendered alongside the beauty image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aov {
+    /// Reflectance of the first surface hit
+    Albedo,
+    /// Shading normal of the first surface hit, facing the camera
+    Normal,
+    /// Distance from the camera to the first surface hit
+    Depth,
+    /// World position of the first surface hit
+    Position,
+    /// One plus the index of the sphere hit first, 0 for the background
+    ObjectId,
+    /// One plus the index of the first sphere with the same material, 0 for
+    /// the background
+    MaterialId,
+    /// Light reaching a diffuse first surface straight from an emitter
+    DirectDiffuse,
+    /// Light reaching a diffuse first surface after further bounces
+    IndirectDiffuse,
+    /// Light reaching a metal or dielectric first surface straight from an
+    /// emitter
+    DirectSpecular,
+    /// Light reaching a metal or dielectric first surface after further
+    /// bounces
+    IndirectSpecular,
+    /// Emitters and background seen directly by the camera
+    Emission,
+}
+
+impl Aov {
+    /// Name of the pass in snake case, used for its layer in multi-layer EXR
+    /// files and as the suffix of its own file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DirectDiffuse => "direct_diffuse",
+            Aov::IndirectDiffuse => "indirect_diffuse",
+            Aov::DirectSpecular => "direct_specular",
+            Aov::IndirectSpecular => "indirect_specular",
+            Aov::Emission => "emission",
+        }
+    }
+
+    /// Names of the channels of the pass in multi-layer EXR files.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            _ => &["R", "G", "B"],
+        }
+    }
+
+    /// Whether the pass holds light, which is exposed like the beauty image
+    /// and adds up with the other light passes to it.
+    pub fn is_light(&self) -> bool {
+        matches!(
+            self,
+            Aov::DirectDiffuse
+                | Aov::IndirectDiffuse
+                | Aov::DirectSpecular
+                | Aov::IndirectSpecular
+                | Aov::Emission
+        )
+    }
+
+    /// Whether the pass is filtered like the beauty image. The others keep
+    /// the sample closest to the center of each pixel, as averaging depths or
+    /// identifiers across an edge gives values belonging to no surface.
+    fn is_filtered(&self) -> bool {
+        !matches!(
+            self,
+            Aov::Depth | Aov::Position | Aov::ObjectId | Aov::MaterialId
+        )
+    }
+}
+
+/// Passes to render with the path tracer and where to write them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AovSettings {
+    pub passes: Vec<Aov>,
+    pub layout: AovLayout,
+    /// Material identifier of every sphere, see `prepare`
+    #[serde(skip)]
+    material_ids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AovLayout {
+    /// A single EXR holding the beauty image and every pass: the output
+    /// itself when it is an EXR, otherwise a `_aovs.exr` file next to it.
+    #[default]
+    Layers,
+    /// One floating point file per pass next to the output, suffixed with
+    /// the name of the pass.
+    Files,
+}
+
+impl AovSettings {
+    /// Numbers the distinct materials of `world`, which must be called again
+    /// whenever the spheres change.
+    pub fn prepare(&mut self, world: &[Sphere]) {
+        self.material_ids = world
+            .iter()
+            .map(|sphere| {
+                let first = world
+                    .iter()
+                    .position(|s| s.material == sphere.material)
+                    .unwrap_or_default();
+                first as u32 + 1
+            })
+            .collect();
+    }
+
+    pub fn material_ids(&self) -> &[u32] {
+        &self.material_ids
+    }
+
+    /// Writes the requested passes among the resolved `passes` along the
+    /// `beauty` image, following the layout and the path and format of `output`.
+    pub fn write(
+        &self,
+        output: &Output,
+        beauty: &[Color],
+        passes: &[(Aov, Vec<Color>)],
+        bounds: (usize, usize),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let format = output.format()?;
+        let passes: Vec<_> = passes
+            .iter()
+            .filter(|(aov, _)| self.passes.contains(aov))
+            .collect();
+        match self.layout {
+            AovLayout::Layers => {
+                let path = match format {
+                    OutputFormat::Exr => output.path.clone(),
+                    _ => sibling_path(&output.path, "aovs", "exr"),
+                };
+                let mut channels: Vec<(String, Vec<f32>)> = ["R", "G", "B"]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| (name.to_string(), channel(beauty, i)))
+                    .collect();
+                for (aov, pixels) in passes {
+                    for (i, name) in aov.channels().iter().enumerate() {
+                        channels.push((format!("{}.{name}", aov.name()), channel(pixels, i)));
+                    }
+                }
+                write_exr_channels(&path, bounds, channels)?;
+            }
+            AovLayout::Files => {
+                // Passes hold linear values, so low dynamic range outputs get EXR passes
+                let (format, extension) = match format {
+                    OutputFormat::Hdr => (format, "hdr"),
+                    OutputFormat::Pfm => (format, "pfm"),
+                    _ => (OutputFormat::Exr, "exr"),
+                };
+                for (aov, pixels) in passes {
+                    let path = sibling_path(&output.path, aov.name(), extension);
+                    write_float_image(&path, format, pixels, bounds)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn channel(pixels: &[Color], index: usize) -> Vec<f32> {
+    pixels.iter().map(|c| c[index] as f32).collect()
+}
+
+/// Values of every pass for one camera sample.
+#[derive(Debug, Clone, Copy)]
+pub struct AovSample {
+    pub albedo: Color,
+    pub normal: Color,
+    pub depth: f64,
+    pub position: Color,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub direct_diffuse: Color,
+    pub indirect_diffuse: Color,
+    pub direct_specular: Color,
+    pub indirect_specular: Color,
+    pub emission: Color,
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        Self {
+            albedo: Color::default(),
+            normal: Color::default(),
+            depth: f64::INFINITY,
+            position: Color::default(),
+            object_id: 0,
+            material_id: 0,
+            direct_diffuse: Color::default(),
+            indirect_diffuse: Color::default(),
+            direct_specular: Color::default(),
+            indirect_specular: Color::default(),
+            emission: Color::default(),
+        }
+    }
+}
+
+impl AovSample {
+    /// Records the geometry of the first surface, sphere number `object`,
+    /// hit by the camera `ray`.
+    pub fn set_first_hit(
+        &mut self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        object: usize,
+        settings: Option<&AovSettings>,
+    ) {
+        self.albedo = match hit_record.material {
+            Material::Lambertian(l) => Color::from(l.albedo),
+            Material::Metal(m) => Color::from(m.albedo),
+            Material::Dielectric(_) | Material::Light(_) => Color::new(1.0, 1.0, 1.0),
+        };
+        self.normal = hit_record.normal;
+        self.depth = (hit_record.p - ray.origin()).norm_squared().sqrt();
+        self.position = hit_record.p;
+        self.object_id = object as u32 + 1;
+        self.material_id = settings
+            .and_then(|s| s.material_ids.get(object).copied())
+            .unwrap_or_default();
+    }
+
+    /// Adds light found after `bounces` surfaces to the light passes, which
+    /// are split by the kind of the first surface.
+    pub fn add_light(&mut self, bounces: u32, diffuse: bool, light: Color) {
+        let pass = match (bounces, diffuse) {
+            (0, _) => &mut self.emission,
+            (1, true) => &mut self.direct_diffuse,
+            (_, true) => &mut self.indirect_diffuse,
+            (1, false) => &mut self.direct_specular,
+            (_, false) => &mut self.indirect_specular,
+        };
+        *pass += light;
+    }
+
+    fn value(&self, aov: Aov) -> Color {
+        let scalar = |v: f64| Color::new(v, v, v);
+        match aov {
+            Aov::Albedo => self.albedo,
+            Aov::Normal => self.normal,
+            Aov::Depth => scalar(self.depth),
+            Aov::Position => self.position,
+            Aov::ObjectId => scalar(self.object_id as f64),
+            Aov::MaterialId => scalar(self.material_id as f64),
+            Aov::DirectDiffuse => self.direct_diffuse,
+            Aov::IndirectDiffuse => self.indirect_diffuse,
+            Aov::DirectSpecular => self.direct_specular,
+            Aov::IndirectSpecular => self.indirect_specular,
+            Aov::Emission => self.emission,
+        }
+    }
+}
+
+/// Passes accumulated over a band of rows of the image, like `Film` does for
+/// the beauty image.
+#[derive(Debug, Clone, Default)]
+pub struct AovFilm {
+    passes: Vec<(Aov, PassBuffer)>,
+}
+
+#[derive(Debug, Clone)]
+enum PassBuffer {
+    Filtered(Film),
+    Closest(ClosestSamples),
+}
+
+impl AovFilm {
+    pub fn new(passes: &[Aov], width: usize, height: usize) -> Self {
+        Self::window(passes, 0, 0, width, height)
+    }
+
+    /// Passes covering a window of the image, see `Film::window`.
+    pub fn window(passes: &[Aov], x0: usize, y0: usize, width: usize, rows: usize) -> Self {
+        let passes = passes
+            .iter()
+            .map(|&aov| {
+                let buffer = if aov.is_filtered() {
+                    PassBuffer::Filtered(Film::window(x0, y0, width, rows))
+                } else {
+                    PassBuffer::Closest(ClosestSamples::window(x0, y0, width, rows))
+                };
+                (aov, buffer)
+            })
+            .collect();
+        Self { passes }
+    }
+
+    /// Band receiving the passes of image row `y`, see `Film::for_row`.
+    pub fn for_row(passes: &[Aov], width: usize, height: usize, y: usize, filter: &Filter) -> Self {
+        Self::for_tile(passes, (width, height), &Tile::row(width, y), filter)
+    }
+
+    /// Window receiving the passes of `tile`, see `Film::for_tile`.
+    pub fn for_tile(passes: &[Aov], bounds: (usize, usize), tile: &Tile, filter: &Filter) -> Self {
+        let (columns, rows) = Film::reached(bounds, tile, filter);
+        Self::window(passes, columns.start, rows.start, columns.len(), rows.len())
+    }
+
+    /// Adds `sample`, seen at the image position `position` inside `pixel`.
+    /// Light passes are turned into film values by `expose`.
+    pub fn splat(
+        &mut self,
+        position: (f64, f64),
+        pixel: (usize, usize),
+        sample: &AovSample,
+        filter: &Filter,
+        expose: impl Fn(Color) -> Color,
+    ) {
+        for (aov, buffer) in self.passes.iter_mut() {
+            let mut value = sample.value(*aov);
+            if aov.is_light() {
+                value = expose(value);
+            }
+            match buffer {
+                PassBuffer::Filtered(film) => film.splat(position, value, filter),
+                PassBuffer::Closest(samples) => {
+                    let dx = position.0 - (pixel.0 as f64 + 0.5);
+                    let dy = position.1 - (pixel.1 as f64 + 0.5);
+                    samples.add(pixel, dx * dx + dy * dy, value);
+                }
+            }
+        }
+    }
+
+    /// Adds the passes of `other`, which must lie within this film.
+    pub fn merge(&mut self, other: &AovFilm) {
+        for ((_, buffer), (_, o)) in self.passes.iter_mut().zip(&other.passes) {
+            match (buffer, o) {
+                (PassBuffer::Filtered(film), PassBuffer::Filtered(o)) => film.merge(o),
+                (PassBuffer::Closest(samples), PassBuffer::Closest(o)) => samples.merge(o),
+                _ => unreachable!("merging films of different passes"),
+            }
+        }
+    }
+
+    /// State of every pass, four numbers per pixel, which `load_raw` restores.
+    pub fn raw(&self) -> Vec<f64> {
+        self.passes
+            .iter()
+            .flat_map(|(_, buffer)| match buffer {
+                PassBuffer::Filtered(film) => film.raw(),
+                PassBuffer::Closest(samples) => samples.raw(),
+            })
+            .collect()
+    }
+
+    /// Replaces the passes with the ones saved by `raw` from a film of the
+    /// same size and passes.
+    pub fn load_raw(&mut self, raw: &[f64]) {
+        let len = raw.len() / self.passes.len().max(1);
+        for ((_, buffer), raw) in self.passes.iter_mut().zip(raw.chunks_exact(len.max(1))) {
+            match buffer {
+                PassBuffer::Filtered(film) => film.load_raw(raw),
+                PassBuffer::Closest(samples) => samples.load_raw(raw),
+            }
+        }
+    }
+
+    /// Pixels of every pass, row by row.
+    pub fn resolve(&self) -> Vec<(Aov, Vec<Color>)> {
+        self.passes
+            .iter()
+            .map(|(aov, buffer)| {
+                let pixels = match buffer {
+                    // Normals and positions are not clamped like colors
+                    PassBuffer::Filtered(film) => film.resolve_signed(),
+                    PassBuffer::Closest(samples) => {
+                        samples.pixels.iter().map(|&(_, value)| value).collect()
+                    }
+                };
+                (*aov, pixels)
+            })
+            .collect()
+    }
+}
+
+/// Value of the sample closest to the center of each pixel.
+#[derive(Debug, Clone)]
+struct ClosestSamples {
+    x0: usize,
+    y0: usize,
+    width: usize,
+    /// Squared distance to the center of the pixel, and value of the sample
+    pixels: Vec<(f64, Color)>,
+}
+
+impl ClosestSamples {
+    fn window(x0: usize, y0: usize, width: usize, rows: usize) -> Self {
+        Self {
+            x0,
+            y0,
+            width,
+            pixels: vec![(f64::INFINITY, Color::default()); width * rows],
+        }
+    }
+
+    fn add(&mut self, (x, y): (usize, usize), distance: f64, value: Color) {
+        let pixel = &mut self.pixels[(y - self.y0) * self.width + x - self.x0];
+        if distance < pixel.0 {
+            *pixel = (distance, value);
+        }
+    }
+
+    fn raw(&self) -> Vec<f64> {
+        self.pixels
+            .iter()
+            .flat_map(|(distance, v)| [*distance, v.x(), v.y(), v.z()])
+            .collect()
+    }
+
+    fn load_raw(&mut self, raw: &[f64]) {
+        for (pixel, r) in self.pixels.iter_mut().zip(raw.chunks_exact(4)) {
+            *pixel = (r[0], Color::new(r[1], r[2], r[3]));
+        }
+    }
+
+    fn merge(&mut self, other: &ClosestSamples) {
+        for (row, others) in other.pixels.chunks_exact(other.width.max(1)).enumerate() {
+            let offset = (other.y0 - self.y0 + row) * self.width + other.x0 - self.x0;
+            for (pixel, o) in self.pixels[offset..].iter_mut().zip(others) {
+                if o.0 < pixel.0 {
+                    *pixel = *o;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::{f64::consts::PI, fmt::Debug, path::Path, sync::Arc};
+
+use palette::Srgb;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    light::{Illuminate, LightSample},
+    material::SrgbAsArray,
+    output::{read_float_image, OutputFormat},
+    sampler::Sampler,
+    sampling::Distribution2D,
+    sky::Sky,
+    utils::{clamp, deg_to_rad},
+    vec3::{functions::luminance, Color, Point3, Vec3},
+};
+
+/// Radiance reaching the camera from rays that escape the scene.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Background {
+    Solid {
+        #[serde_as(as = "SrgbAsArray")]
+        color: Srgb,
+    },
+    Gradient {
+        #[serde_as(as = "SrgbAsArray")]
+        bottom: Srgb,
+        #[serde_as(as = "SrgbAsArray")]
+        top: Srgb,
+    },
+    Map(EnvironmentMap),
+    Sky(Sky),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::Gradient {
+            bottom: Srgb::new(1.0, 1.0, 1.0),
+            top: Srgb::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Background {
+    pub fn radiance(&self, dir: Vec3) -> Color {
+        match self {
+            Background::Solid { color } => Color::from(*color),
+            Background::Gradient { bottom, top } => {
+                let t = clamp(0.5 * (dir.unit_vec().y() + 1.0), 0., 1.);
+                (1.0 - t) * Color::from(*bottom) + t * Color::from(*top)
+            }
+            Background::Map(map) => map.radiance(dir),
+            Background::Sky(sky) => sky.radiance(dir),
+        }
+    }
+
+    /// Whether direct lighting samples the background, in which case paths
+    /// leaving diffuse surfaces must not count it again.
+    pub fn is_sampled(&self) -> bool {
+        matches!(self, Background::Map(_) | Background::Sky(_))
+    }
+
+    /// Reads the image files the background refers to.
+    pub fn load(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Background::Map(map) => map.load(),
+            Background::Sky(sky) => {
+                sky.load();
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Illuminate for Background {
+    fn sample(&self, p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        match self {
+            Background::Map(map) => map.sample(p, sampler),
+            Background::Sky(sky) => sky.sample(p, sampler),
+            _ => None,
+        }
+    }
+}
+
+/// Equirectangular environment read from a Radiance `.hdr` or OpenEXR file.
+///
+/// The center of the image lies towards -z, `rotation` turns it around the
+/// y axis (in degrees) and `intensity` scales its radiance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvironmentMap {
+    pub path: String,
+    #[serde(default)]
+    pub rotation: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(skip)]
+    data: Option<Arc<EnvironmentData>>,
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+impl EnvironmentMap {
+    pub fn new(path: &str, rotation: f64, intensity: f64) -> Self {
+        Self {
+            path: path.to_string(),
+            rotation,
+            intensity,
+            data: None,
+        }
+    }
+
+    pub fn load(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (width, height, pixels) = read_hdr_image(&self.path)?;
+        self.data = Some(Arc::new(EnvironmentData::new(width, height, pixels)));
+        Ok(())
+    }
+
+    fn data(&self) -> &EnvironmentData {
+        self.data
+            .as_ref()
+            .expect("environment map used before being loaded")
+    }
+
+    pub fn radiance(&self, dir: Vec3) -> Color {
+        let (u, v) = direction_to_uv(dir, self.rotation);
+        self.intensity * self.data().lookup(u, v)
+    }
+}
+
+impl Illuminate for EnvironmentMap {
+    fn sample(&self, _p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let data = self.data();
+        let (dir, (u, v), pdf) = data.sample_direction(self.rotation, sampler.get_2d())?;
+
+        Some(LightSample {
+            dir,
+            distance: f64::INFINITY,
+            radiance: self.intensity / pdf * data.lookup(u, v),
+        })
+    }
+}
+
+/// Equirectangular image of radiance together with the distribution used to
+/// pick directions proportionally to its luminance.
+pub struct EnvironmentData {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    distribution: Distribution2D,
+}
+
+impl Debug for EnvironmentData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EnvironmentData({}x{})", self.width, self.height)
+    }
+}
+
+impl EnvironmentData {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        // Rows near the poles cover less solid angle than the ones at the horizon
+        let func: Vec<f64> = (0..height)
+            .flat_map(|y| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                pixels[y * width..(y + 1) * width]
+                    .iter()
+                    .map(move |c| luminance(*c) * sin_theta)
+            })
+            .collect();
+        let distribution = Distribution2D::new(&func, width, height);
+
+        Self {
+            width,
+            height,
+            pixels,
+            distribution,
+        }
+    }
+
+    pub fn lookup(&self, u: f64, v: f64) -> Color {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+
+    /// Luminance of the whole map integrated over the sphere of directions.
+    pub fn power(&self) -> f64 {
+        2.0 * PI * PI * self.distribution.integral()
+    }
+
+    /// Picks a direction proportionally to the luminance of the map from `u`
+    /// in [0, 1)², returning it with its image coordinates and its solid angle
+    /// density.
+    pub fn sample_direction(
+        &self,
+        rotation: f64,
+        (u1, u2): (f64, f64),
+    ) -> Option<(Vec3, (f64, f64), f64)> {
+        let ((u, v), map_pdf) = self.distribution.sample_continuous(u1, u2);
+        let sin_theta = (v * PI).sin();
+        if map_pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+
+        // Change of variables from the image square to solid angle
+        let pdf = map_pdf / (2.0 * PI * PI * sin_theta);
+        Some((uv_to_direction(u, v, rotation), (u, v), pdf))
+    }
+}
+
+/// Equirectangular coordinates in [0, 1)² of a direction, with the center of
+/// the image towards -z once turned by `rotation` degrees around y.
+pub fn direction_to_uv(dir: Vec3, rotation: f64) -> (f64, f64) {
+    let d = dir.unit_vec();
+    let phi = d.x().atan2(-d.z()) - deg_to_rad(rotation);
+    let theta = clamp(d.y(), -1.0, 1.0).acos();
+    let u = phi / (2.0 * PI) + 0.5;
+    (u - u.floor(), theta / PI)
+}
+
+pub fn uv_to_direction(u: f64, v: f64, rotation: f64) -> Vec3 {
+    let phi = (u - 0.5) * 2.0 * PI + deg_to_rad(rotation);
+    let theta = v * PI;
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+/// Reads a linear floating point image, returning its size and pixels row by
+/// row: OpenEXR, portable float map or, for any other extension, Radiance.
+pub fn read_hdr_image(
+    path: &str,
+) -> Result<(usize, usize, Vec<Color>), Box<dyn std::error::Error>> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    let format = match extension.as_deref() {
+        Some("exr") => OutputFormat::Exr,
+        Some("pfm") => OutputFormat::Pfm,
+        _ => OutputFormat::Hdr,
+    };
+    read_float_image(path, format)
+}
@@ -1,23 +1,163 @@
 use serde::{
     de::{self, MapAccess, Visitor},
+    ser::SerializeStruct,
     Deserialize, Serialize,
 };
 
+use std::f64::consts::PI;
+
 use crate::{
+    exposure::{self, Exposure, Matrix3},
+    light::concentric_sample_disk,
     ray::Ray,
+    sampler::Sampler,
     utils::deg_to_rad,
     vec3::{
         functions::{cross, unit_vec},
-        Point3, Vec3,
+        Color, Point3, Vec3,
     },
 };
 
-#[derive(Debug, Serialize)]
+/// Thin lens in front of the sensor. An `aperture` of zero gives a pinhole.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Lens {
+    /// Diameter of the lens opening, in scene units
+    pub aperture: f64,
+    /// Distance of the plane in focus, `None` to focus on `look_at`
+    pub focus_dist: Option<f64>,
+    /// Number of diaphragm blades shaping the bokeh, 0 for a round opening
+    pub blades: u32,
+    /// Rotation of the polygonal opening, in degrees
+    pub blade_rotation: f64,
+}
+
+impl Lens {
+    /// Point on the unit-radius opening, uniformly distributed over its area
+    /// as `u` covers [0, 1)².
+    fn sample_aperture(&self, (u1, u2): (f64, f64)) -> (f64, f64) {
+        if self.blades < 3 {
+            return concentric_sample_disk(u1, u2);
+        }
+
+        // Pick one of the equal triangles fanning out of the center, then a
+        // point inside it
+        let n = self.blades as f64;
+        let u = u1 * n;
+        let sector = u.floor().min(n - 1.0);
+        let r2 = u - sector;
+        let r1 = u2.sqrt();
+
+        let angle = |i: f64| deg_to_rad(self.blade_rotation) + 2.0 * PI * i / n;
+        let (a, b) = (angle(sector), angle(sector + 1.0));
+        (
+            r1 * ((1.0 - r2) * a.cos() + r2 * b.cos()),
+            r1 * ((1.0 - r2) * a.sin() + r2 * b.sin()),
+        )
+    }
+}
+
+/// How directions leaving the camera map to the image.
+///
+/// The panoramic projections ignore `vfov` and expect the aspect ratio of
+/// their layout: 2 for equirectangular and 6 for the cube map strip.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    /// Parallel rays covering `height` scene units vertically.
+    Orthographic { height: f64 },
+    /// Equidistant fisheye fitting `fov` degrees in a circle as tall as the image.
+    Fisheye { fov: f64 },
+    /// Full sphere of directions, with `look_at` in the middle of the image.
+    Equirectangular,
+    /// Six 90° faces side by side, in the +x, -x, +y, -y, +z, -z order of
+    /// the camera frame where -z points at `look_at`.
+    Cubemap,
+}
+
+/// Placement of the two views of a stereo pair in the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StereoLayout {
+    /// Left eye on the left half
+    #[default]
+    SideBySide,
+    /// Left eye on the top half
+    TopBottom,
+}
+
+/// Renders a view for each eye, both packed in the same image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    /// Interpupillary distance, in scene units
+    pub ipd: f64,
+    /// Distance at which the views of the eyes cross, `None` for parallel views
+    pub convergence: Option<f64>,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self {
+            layout: StereoLayout::SideBySide,
+            ipd: 0.064,
+            convergence: None,
+        }
+    }
+}
+
+impl Stereo {
+    /// Maps image coordinates to the ones inside the view of an eye, along
+    /// with the offset of that eye from the center of the camera.
+    fn eye(&self, s: f64, t: f64) -> (f64, f64, f64) {
+        let half = self.ipd / 2.0;
+        match self.layout {
+            StereoLayout::SideBySide if s < 0.5 => (2.0 * s, t, -half),
+            StereoLayout::SideBySide => (2.0 * s - 1.0, t, half),
+            StereoLayout::TopBottom if t >= 0.5 => (s, 2.0 * t - 1.0, -half),
+            StereoLayout::TopBottom => (s, 2.0 * t, half),
+        }
+    }
+}
+
+/// Placement and optics of the camera, the parameters an animation changes.
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: Option<f64>,
+}
+
+/// Camera built from its authoring parameters, which are what gets
+/// serialized; everything else is derived from them.
+#[derive(Debug)]
 pub struct Camera {
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+    vfov: f64,
+
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
     vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    aspect_ratio: f64,
+    focus_dist: f64,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    lens: Lens,
+    /// Interval of the frame, from 0 to 1, during which the sensor is exposed
+    shutter_open: f64,
+    shutter_close: f64,
+    exposure: Option<Exposure>,
+    /// White balance and exposure scale applied to the film
+    film_matrix: Matrix3,
 }
 
 impl Camera {
@@ -27,6 +167,17 @@ impl Camera {
         vup: Vec3,
         vfov: f64,
         aspect_ratio: f64,
+    ) -> Self {
+        Self::with_lens(look_from, look_at, vup, vfov, aspect_ratio, Lens::default())
+    }
+
+    pub fn with_lens(
+        look_from: Point3,
+        look_at: Point3,
+        vup: Vec3,
+        vfov: f64,
+        aspect_ratio: f64,
+        lens: Lens,
     ) -> Self {
         let theta = deg_to_rad(vfov);
         let h = f64::tan(theta / 2.);
@@ -37,27 +188,316 @@ impl Camera {
         let u = unit_vec(cross(vup, w));
         let v = cross(w, u);
 
+        // The viewport lies on the plane in focus, where lens rays converge
+        let focus_dist = lens
+            .focus_dist
+            .unwrap_or_else(|| (look_from - look_at).norm());
+
         let origin = look_from;
-        let horizontal = viewport_width * u;
-        let vertical = viewport_height * v;
-        let lower_left_corner = origin - (horizontal / 2.0) - (vertical / 2.0) - w;
+        let horizontal = focus_dist * viewport_width * u;
+        let vertical = focus_dist * viewport_height * v;
+        let lower_left_corner = origin - (horizontal / 2.0) - (vertical / 2.0) - focus_dist * w;
 
         Self {
+            look_from,
+            look_at,
+            vup,
+            vfov,
             origin,
             lower_left_corner,
             horizontal,
             vertical,
+            u,
+            v,
+            w,
+            aspect_ratio,
+            focus_dist,
+            projection: Projection::Perspective,
+            stereo: None,
+            exposure: None,
+            film_matrix: exposure::IDENTITY,
+            lens,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// Exposes the sensor from `open` to `close`, blurring whatever moves in between.
+    pub fn set_shutter(&mut self, open: f64, close: f64) {
+        self.shutter_open = open;
+        self.shutter_close = close;
+    }
+
+    pub fn shutter(&self) -> (f64, f64) {
+        (self.shutter_open, self.shutter_close)
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Splits the image between the two eyes, each view keeping the camera's
+    /// own aspect ratio.
+    pub fn set_stereo(&mut self, stereo: Option<Stereo>) {
+        self.stereo = stereo;
+    }
+
+    pub fn stereo(&self) -> Option<Stereo> {
+        self.stereo
+    }
+
+    /// Sets the photographic exposure, whose f-stop replaces the aperture of the lens.
+    pub fn set_exposure(&mut self, exposure: Option<Exposure>) {
+        self.exposure = exposure;
+        match exposure {
+            Some(e) => {
+                self.lens.aperture = e.aperture(self.vfov);
+                self.film_matrix = e.film_matrix();
+            }
+            None => self.film_matrix = exposure::IDENTITY,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        Ray::new(
-            self.origin,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin,
+    pub fn exposure(&self) -> Option<Exposure> {
+        self.exposure
+    }
+
+    /// Turns the radiance reaching the sensor into film values.
+    pub fn expose(&self, radiance: Color) -> Color {
+        exposure::transform(&self.film_matrix, radiance)
+    }
+
+    pub fn lens(&self) -> &Lens {
+        &self.lens
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            look_from: self.look_from,
+            look_at: self.look_at,
+            vup: self.vup,
+            vfov: self.vfov,
+            aperture: self.lens.aperture,
+            focus_dist: self.lens.focus_dist,
+        }
+    }
+
+    /// Moves the camera, keeping its projection, shutter and exposure, whose
+    /// f-stop still sets the aperture.
+    pub fn set_view(&mut self, view: View) {
+        let lens = Lens {
+            aperture: view.aperture,
+            focus_dist: view.focus_dist,
+            ..self.lens
+        };
+        let mut camera = Camera::with_lens(
+            view.look_from,
+            view.look_at,
+            view.vup,
+            view.vfov,
+            self.aspect_ratio,
+            lens,
+        );
+        camera.set_shutter(self.shutter_open, self.shutter_close);
+        camera.set_projection(self.projection);
+        camera.set_stereo(self.stereo);
+        camera.set_exposure(self.exposure);
+        *self = camera;
+    }
+
+    /// Ray through the image point (`s`, `t`), measured from the bottom left
+    /// corner. Points outside the image circle of a fisheye give `None`.
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        // Drawn even when unused, so that the dimensions of the path do not
+        // depend on the camera settings
+        let u_time = sampler.get_1d();
+        let u_lens = sampler.get_2d();
+
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + u_time * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        };
+
+        let (s, t, eye) = match self.stereo {
+            Some(stereo) => stereo.eye(s, t),
+            None => (s, t, 0.0),
+        };
+        let convergence = self.stereo.and_then(|stereo| stereo.convergence);
+
+        let (origin, dir) = match self.projection {
+            Projection::Perspective => {
+                let (origin, dir) = self.perspective_ray(s, t, u_lens);
+                // Off-axis views sharing the plane at the convergence distance
+                let shift = match convergence {
+                    Some(c) => eye * self.focus_dist / c * self.u,
+                    None => Vec3::default(),
+                };
+                (origin + eye * self.u, dir - shift)
+            }
+            Projection::Orthographic { height } => {
+                let width = self.aspect_ratio * height;
+                let origin = self.origin + (s - 0.5) * width * self.u + (t - 0.5) * height * self.v;
+                (origin + eye * self.u, -self.w)
+            }
+            Projection::Fisheye { fov } => {
+                let x = (2.0 * s - 1.0) * self.aspect_ratio;
+                let y = 2.0 * t - 1.0;
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                // Angle from the optical axis grows linearly with the distance from the center
+                let theta = (r * deg_to_rad(fov) / 2.0).min(PI);
+                let (cos_phi, sin_phi) = if r > 0.0 { (x / r, y / r) } else { (1.0, 0.0) };
+                let local = Vec3::new(theta.sin() * cos_phi, theta.sin() * sin_phi, -theta.cos());
+                self.eye_ray(self.to_world(local), eye * self.u, convergence)
+            }
+            Projection::Equirectangular => {
+                let phi = (s - 0.5) * 2.0 * PI;
+                let theta = (1.0 - t) * PI;
+                let local = Vec3::new(
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    -theta.sin() * phi.cos(),
+                );
+                // Omni-directional stereo: the eyes turn with the view, staying
+                // on a circle perpendicular to each horizontal direction
+                let right = self.to_world(Vec3::new(phi.cos(), 0.0, phi.sin()));
+                self.eye_ray(self.to_world(local), eye * right, convergence)
+            }
+            Projection::Cubemap => {
+                let face = (s * 6.0).floor().clamp(0.0, 5.0);
+                let a = 2.0 * (s * 6.0 - face) - 1.0;
+                let b = 2.0 * t - 1.0;
+                // Each face is seen from the inside, with +y up on the side faces
+                // and the top and bottom faces meeting the -z face
+                let local = match face as usize {
+                    0 => Vec3::new(1.0, b, a),
+                    1 => Vec3::new(-1.0, b, -a),
+                    2 => Vec3::new(a, 1.0, b),
+                    3 => Vec3::new(a, -1.0, -b),
+                    4 => Vec3::new(-a, b, 1.0),
+                    _ => Vec3::new(a, b, -1.0),
+                };
+                self.eye_ray(self.to_world(local), eye * self.u, convergence)
+            }
+        };
+
+        Some(Ray::with_time(origin, dir, time))
+    }
+
+    /// Ray leaving the eye at `offset` from the camera, turned to meet the ray
+    /// of the other eye at the convergence distance.
+    fn eye_ray(&self, dir: Vec3, offset: Vec3, convergence: Option<f64>) -> (Point3, Vec3) {
+        match convergence {
+            Some(c) => (self.origin + offset, c * unit_vec(dir) - offset),
+            None => (self.origin + offset, dir),
+        }
+    }
+
+    fn to_world(&self, local: Vec3) -> Vec3 {
+        local.x() * self.u + local.y() * self.v + local.z() * self.w
+    }
+
+    /// Pinhole or thin lens ray, returned as an origin and a direction.
+    fn perspective_ray(&self, s: f64, t: f64, u_lens: (f64, f64)) -> (Point3, Vec3) {
+        let offset = if self.lens.aperture > 0.0 {
+            let (dx, dy) = self.lens.sample_aperture(u_lens);
+            self.lens.aperture / 2.0 * (dx * self.u + dy * self.v)
+        } else {
+            Vec3::default()
+        };
+
+        (
+            self.origin + offset,
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
         )
     }
 }
 
+impl Serialize for Camera {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // Mirrors the fields read by the deserializer, leaving out the defaults
+        let mut state = serializer.serialize_struct("Camera", FIELDS.len())?;
+        state.serialize_field("look_from", &self.look_from)?;
+        state.serialize_field("look_at", &self.look_at)?;
+        state.serialize_field("vup", &self.vup)?;
+        state.serialize_field("vfov", &self.vfov)?;
+        state.serialize_field("aspect_ratio", &self.aspect_ratio)?;
+
+        let lens = &self.lens;
+        // With an exposure the aperture follows from its f-stop
+        if lens.aperture != 0.0 && self.exposure.is_none() {
+            state.serialize_field("aperture", &lens.aperture)?;
+        } else {
+            state.skip_field("aperture")?;
+        }
+        match lens.focus_dist {
+            Some(focus_dist) => state.serialize_field("focus_dist", &focus_dist)?,
+            None => state.skip_field("focus_dist")?,
+        }
+        state.skip_field("autofocus")?;
+        if lens.blades != 0 {
+            state.serialize_field("blades", &lens.blades)?;
+        } else {
+            state.skip_field("blades")?;
+        }
+        if lens.blade_rotation != 0.0 {
+            state.serialize_field("blade_rotation", &lens.blade_rotation)?;
+        } else {
+            state.skip_field("blade_rotation")?;
+        }
+
+        if self.shutter_open != 0.0 || self.shutter_close != 0.0 {
+            state.serialize_field("shutter_open", &self.shutter_open)?;
+            state.serialize_field("shutter_close", &self.shutter_close)?;
+        } else {
+            state.skip_field("shutter_open")?;
+            state.skip_field("shutter_close")?;
+        }
+        if self.projection != Projection::default() {
+            state.serialize_field("projection", &self.projection)?;
+        } else {
+            state.skip_field("projection")?;
+        }
+        match &self.stereo {
+            Some(stereo) => state.serialize_field("stereo", stereo)?,
+            None => state.skip_field("stereo")?,
+        }
+        match &self.exposure {
+            Some(exposure) => state.serialize_field("exposure", exposure)?,
+            None => state.skip_field("exposure")?,
+        }
+        state.end()
+    }
+}
+
+const FIELDS: &[&str] = &[
+    "look_from",
+    "look_at",
+    "vup",
+    "vfov",
+    "aspect_ratio",
+    "aperture",
+    "focus_dist",
+    "autofocus",
+    "blades",
+    "blade_rotation",
+    "shutter_open",
+    "shutter_close",
+    "projection",
+    "stereo",
+    "exposure",
+];
+
 impl<'de> Deserialize<'de> for Camera {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -69,6 +509,16 @@ impl<'de> Deserialize<'de> for Camera {
             Vup,
             Vfov,
             AspectRatio,
+            Aperture,
+            FocusDist,
+            Autofocus,
+            Blades,
+            BladeRotation,
+            ShutterOpen,
+            ShutterClose,
+            Projection,
+            Stereo,
+            Exposure,
         }
 
         impl<'de> Deserialize<'de> for Field {
@@ -82,7 +532,9 @@ impl<'de> Deserialize<'de> for Camera {
                     type Value = Field;
 
                     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                        formatter.write_str("look_from, look_at, vup, vfov, aspect_ratio")
+                        formatter.write_str(
+                            "look_from, look_at, vup, vfov, aspect_ratio, aperture, focus_dist, autofocus, blades, blade_rotation, shutter_open, shutter_close, projection, stereo, exposure",
+                        )
                     }
 
                     fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -95,6 +547,16 @@ impl<'de> Deserialize<'de> for Camera {
                             "vup" => Ok(Field::Vup),
                             "vfov" => Ok(Field::Vfov),
                             "aspect_ratio" => Ok(Field::AspectRatio),
+                            "aperture" => Ok(Field::Aperture),
+                            "focus_dist" => Ok(Field::FocusDist),
+                            "autofocus" => Ok(Field::Autofocus),
+                            "blades" => Ok(Field::Blades),
+                            "blade_rotation" => Ok(Field::BladeRotation),
+                            "shutter_open" => Ok(Field::ShutterOpen),
+                            "shutter_close" => Ok(Field::ShutterClose),
+                            "projection" => Ok(Field::Projection),
+                            "stereo" => Ok(Field::Stereo),
+                            "exposure" => Ok(Field::Exposure),
                             _ => Err(de::Error::unknown_field(value, FIELDS)),
                         }
                     }
@@ -113,29 +575,9 @@ impl<'de> Deserialize<'de> for Camera {
                 formatter.write_str("struct Camera")
             }
 
-            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
-            where
-                A: de::SeqAccess<'de>,
-            {
-                let look_from = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
-                let look_at = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let vup = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
-                let vfov = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(3, &self))?;
-                let aspect_ratio = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(4, &self))?;
-
-                Ok(Camera::new(look_from, look_at, vup, vfov, aspect_ratio))
-            }
-
+            // Only maps are read: the serializer leaves out the optional
+            // fields holding defaults, so their position in a sequence would
+            // not tell which they are
             fn visit_map<V>(self, mut map: V) -> Result<Camera, V::Error>
             where
                 V: MapAccess<'de>,
@@ -145,41 +587,121 @@ impl<'de> Deserialize<'de> for Camera {
                 let mut vup = None;
                 let mut vfov = None;
                 let mut aspect_ratio = None;
+                let mut aperture = None;
+                let mut focus_dist = None;
+                let mut autofocus = None;
+                let mut blades = None;
+                let mut blade_rotation = None;
+                let mut shutter_open = None;
+                let mut shutter_close = None;
+                let mut projection = None;
+                let mut stereo = None;
+                let mut exposure: Option<Exposure> = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::LookAt => {
                             if look_at.is_some() {
-                                return Err(de::Error::duplicate_field("secs"));
+                                return Err(de::Error::duplicate_field("look_at"));
                             }
                             look_at = Some(map.next_value()?);
                         }
                         Field::LookFrom => {
                             if look_from.is_some() {
-                                return Err(de::Error::duplicate_field("nanos"));
+                                return Err(de::Error::duplicate_field("look_from"));
                             }
                             look_from = Some(map.next_value()?);
                         }
 
                         Field::Vup => {
                             if vup.is_some() {
-                                return Err(de::Error::duplicate_field("nanos"));
+                                return Err(de::Error::duplicate_field("vup"));
                             }
                             vup = Some(map.next_value()?);
                         }
 
                         Field::Vfov => {
                             if vfov.is_some() {
-                                return Err(de::Error::duplicate_field("nanos"));
+                                return Err(de::Error::duplicate_field("vfov"));
                             }
                             vfov = Some(map.next_value()?);
                         }
 
                         Field::AspectRatio => {
                             if aspect_ratio.is_some() {
-                                return Err(de::Error::duplicate_field("nanos"));
+                                return Err(de::Error::duplicate_field("aspect_ratio"));
                             }
                             aspect_ratio = Some(map.next_value()?);
                         }
+
+                        Field::Aperture => {
+                            if aperture.is_some() {
+                                return Err(de::Error::duplicate_field("aperture"));
+                            }
+                            aperture = Some(map.next_value()?);
+                        }
+
+                        Field::FocusDist => {
+                            if focus_dist.is_some() {
+                                return Err(de::Error::duplicate_field("focus_dist"));
+                            }
+                            focus_dist = Some(map.next_value()?);
+                        }
+
+                        Field::Autofocus => {
+                            if autofocus.is_some() {
+                                return Err(de::Error::duplicate_field("autofocus"));
+                            }
+                            autofocus = Some(map.next_value()?);
+                        }
+
+                        Field::Blades => {
+                            if blades.is_some() {
+                                return Err(de::Error::duplicate_field("blades"));
+                            }
+                            blades = Some(map.next_value()?);
+                        }
+
+                        Field::BladeRotation => {
+                            if blade_rotation.is_some() {
+                                return Err(de::Error::duplicate_field("blade_rotation"));
+                            }
+                            blade_rotation = Some(map.next_value()?);
+                        }
+
+                        Field::ShutterOpen => {
+                            if shutter_open.is_some() {
+                                return Err(de::Error::duplicate_field("shutter_open"));
+                            }
+                            shutter_open = Some(map.next_value()?);
+                        }
+
+                        Field::ShutterClose => {
+                            if shutter_close.is_some() {
+                                return Err(de::Error::duplicate_field("shutter_close"));
+                            }
+                            shutter_close = Some(map.next_value()?);
+                        }
+
+                        Field::Projection => {
+                            if projection.is_some() {
+                                return Err(de::Error::duplicate_field("projection"));
+                            }
+                            projection = Some(map.next_value()?);
+                        }
+
+                        Field::Stereo => {
+                            if stereo.is_some() {
+                                return Err(de::Error::duplicate_field("stereo"));
+                            }
+                            stereo = Some(map.next_value()?);
+                        }
+
+                        Field::Exposure => {
+                            if exposure.is_some() {
+                                return Err(de::Error::duplicate_field("exposure"));
+                            }
+                            exposure = Some(map.next_value()?);
+                        }
                     }
                 }
                 let look_from = look_from.ok_or_else(|| de::Error::missing_field("look_from"))?;
@@ -188,11 +710,46 @@ impl<'de> Deserialize<'de> for Camera {
                 let vfov = vfov.ok_or_else(|| de::Error::missing_field("vfov"))?;
                 let aspect_ratio =
                     aspect_ratio.ok_or_else(|| de::Error::missing_field("aspect_ratio"))?;
-                Ok(Camera::new(look_from, look_at, vup, vfov, aspect_ratio))
+
+                // Without an explicit distance the camera focuses on look_at
+                let focus_dist = match (focus_dist, autofocus) {
+                    (Some(_), Some(true)) => {
+                        return Err(de::Error::custom(
+                            "focus_dist cannot be set together with autofocus",
+                        ))
+                    }
+                    (None, Some(false)) => return Err(de::Error::missing_field("focus_dist")),
+                    (focus_dist, _) => focus_dist,
+                };
+                if aperture.is_some() && exposure.is_some() {
+                    return Err(de::Error::custom(
+                        "aperture cannot be set together with exposure, which uses its f_stop",
+                    ));
+                }
+                let lens = Lens {
+                    aperture: aperture.unwrap_or(0.0),
+                    focus_dist,
+                    blades: blades.unwrap_or(0),
+                    blade_rotation: blade_rotation.unwrap_or(0.0),
+                };
+                let shutter_open = shutter_open.unwrap_or(0.0);
+                let shutter_close = shutter_close.unwrap_or(shutter_open);
+                if shutter_close < shutter_open {
+                    return Err(de::Error::custom(
+                        "shutter_close must not come before shutter_open",
+                    ));
+                }
+
+                let mut camera =
+                    Camera::with_lens(look_from, look_at, vup, vfov, aspect_ratio, lens);
+                camera.set_shutter(shutter_open, shutter_close);
+                camera.set_projection(projection.unwrap_or_default());
+                camera.set_stereo(stereo);
+                camera.set_exposure(exposure);
+                Ok(camera)
             }
         }
 
-        const FIELDS: &[&str] = &["look_from", "look_at", "vup", "vfov", "aspect_ratio"];
         deserializer.deserialize_struct("Camera", FIELDS, CameraVisitor)
     }
 }
@@ -0,0 +1,234 @@
+use std::ops::RangeInclusive;
+
+use crate::{
+    crop::{Crop, CropWindow},
+    output::{Output, OutputFormat},
+    renderer::Integrator,
+    state::State,
+};
+
+pub const USAGE: &str = "\
+Usage: ray_tracer [OPTIONS]
+
+Options:
+    -s, --state <FILE>     State file describing the render [default: state.json]
+    -o, --output <FILE>    Image to write, overriding the state file
+    -f, --format <FORMAT>  png, png16, jpeg, bmp, tga, ppm, exr, hdr or pfm,
+                           guessed from the output extension by default
+    -q, --quality <1-100>  JPEG quality [default: 90]
+        --denoise          Denoise the image, with the settings of the state
+                           file if it has any
+        --no-denoise       Keep the noisy image even if the state file asks
+                           for denoising
+        --resume           Continue the progressive render saved in the
+                           checkpoint
+        --samples <N>      Samples per pixel at which progressive rendering
+                           stops
+        --time-limit <SECONDS>
+                           Time after which progressive rendering stops
+        --crop <LEFT,TOP,RIGHT,BOTTOM>
+                           Render only this part of the frame, in pixels or,
+                           with decimals, in fractions of the frame
+        --composite        Paste the crop into the existing output images
+        --frames <FIRST[-LAST]>
+                           Render only these frames of an animation,
+                           numbered from 1
+        --workers <ADDR,...>
+                           Render the tiles on the workers listening on
+                           these addresses
+        --worker <ADDR>    Listen on this address and render the tiles
+                           coordinators send, ignoring the state file
+    -h, --help             Print this message";
+
+/// Options given on the command line, which take precedence over the state file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub state: Option<String>,
+    pub output: Option<String>,
+    pub format: Option<OutputFormat>,
+    pub quality: Option<u8>,
+    pub denoise: Option<bool>,
+    pub resume: bool,
+    pub samples: Option<u32>,
+    pub time_limit: Option<f64>,
+    pub crop: Option<CropWindow>,
+    pub composite: bool,
+    pub frames: Option<(u32, u32)>,
+    pub workers: Option<Vec<String>>,
+    pub worker: Option<String>,
+    pub help: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "--denoise" => parsed.denoise = Some(true),
+                "--no-denoise" => parsed.denoise = Some(false),
+                "--resume" => parsed.resume = true,
+                "--composite" => parsed.composite = true,
+                "--crop" => parsed.crop = Some(CropWindow::parse(&value()?)?),
+                "--frames" => {
+                    let value = value()?;
+                    let (first, last) = value.split_once('-').unwrap_or((&value, &value));
+                    let frames = first
+                        .parse()
+                        .ok()
+                        .zip(last.parse().ok())
+                        .filter(|&(first, last)| 0 < first && first <= last)
+                        .ok_or_else(|| format!("invalid frame range {value}"))?;
+                    parsed.frames = Some(frames);
+                }
+                "--worker" => parsed.worker = Some(value()?),
+                "--workers" => {
+                    let value = value()?;
+                    let workers: Vec<String> = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|w| !w.is_empty())
+                        .map(String::from)
+                        .collect();
+                    if workers.is_empty() {
+                        return Err(format!("invalid worker list {value}"));
+                    }
+                    parsed.workers = Some(workers);
+                }
+                "-s" | "--state" => parsed.state = Some(value()?),
+                "-o" | "--output" => parsed.output = Some(value()?),
+                "-f" | "--format" => {
+                    let value = value()?;
+                    let format = OutputFormat::from_name(&value)
+                        .ok_or_else(|| format!("unknown image format {value}"))?;
+                    parsed.format = Some(format);
+                }
+                "-q" | "--quality" => {
+                    let value = value()?;
+                    let quality = value
+                        .parse()
+                        .ok()
+                        .filter(|q| (1..=100).contains(q))
+                        .ok_or_else(|| format!("invalid JPEG quality {value}"))?;
+                    parsed.quality = Some(quality);
+                }
+                "--samples" => {
+                    let value = value()?;
+                    let samples = value
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("invalid number of samples {value}"))?;
+                    parsed.samples = Some(samples);
+                }
+                "--time-limit" => {
+                    let value = value()?;
+                    let seconds = value
+                        .parse()
+                        .ok()
+                        .filter(|&t: &f64| t >= 0.0)
+                        .ok_or_else(|| format!("invalid time limit {value}"))?;
+                    parsed.time_limit = Some(seconds);
+                }
+                _ => return Err(format!("unknown option {arg}")),
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn state_file(&self) -> &str {
+        self.state.as_deref().unwrap_or("state.json")
+    }
+
+    /// Frames to render, all of them by default.
+    pub fn frame_range(&self, state: &State) -> RangeInclusive<u32> {
+        let (first, last) = self.frames.unwrap_or((1, state.frames.max(1)));
+        first..=last
+    }
+
+    /// Replaces the settings of `state` with the ones given, checking that
+    /// the output format is known before anything is rendered.
+    pub fn apply(&self, state: &mut State) -> Result<(), String> {
+        match self.denoise {
+            Some(true) => {
+                state.denoise.get_or_insert_with(Default::default);
+            }
+            Some(false) => state.denoise = None,
+            None => {}
+        }
+        if self.resume || self.samples.is_some() || self.time_limit.is_some() {
+            let progressive = state.progressive.get_or_insert_with(Default::default);
+            progressive.resume |= self.resume;
+            if self.samples.is_some() {
+                progressive.target_samples = self.samples;
+            }
+            if self.time_limit.is_some() {
+                progressive.time_limit = self.time_limit;
+            }
+        }
+        if let Some(window) = self.crop {
+            state.crop = Some(Crop {
+                window,
+                composite: false,
+            });
+        }
+        if self.composite {
+            let crop = state
+                .crop
+                .as_mut()
+                .ok_or("compositing needs a crop window")?;
+            crop.composite = true;
+        }
+        if let Some(crop) = &state.crop {
+            crop.tile((state.width.unwrap() as usize, state.height as usize))?;
+        }
+        if let Some((_, last)) = self.frames {
+            if last > state.frames {
+                return Err(format!("the animation only has {} frames", state.frames));
+            }
+        }
+        if let Integrator::Metropolis(config) = &state.integrator {
+            if config.mutations_per_pixel == 0 {
+                return Err("Metropolis light transport needs mutations_per_pixel".to_string());
+            }
+        }
+        if let Some(animation) = &state.animation {
+            animation.check(state)?;
+        }
+        if let Some(workers) = &self.workers {
+            let distributed = state.distributed.get_or_insert_with(Default::default);
+            distributed.workers = workers.clone();
+        }
+        if state
+            .distributed
+            .as_ref()
+            .is_some_and(|d| d.workers.is_empty())
+        {
+            return Err("a distributed render needs workers".to_string());
+        }
+        if let Some(path) = &self.output {
+            // A new path gets its format from its own extension
+            state.output = Output {
+                path: path.clone(),
+                format: None,
+            };
+        }
+        if let Some(format) = self.format {
+            state.output.format = Some(format);
+        }
+        if let Some(quality) = self.quality {
+            match state.output.format()? {
+                OutputFormat::Jpeg { .. } => {
+                    state.output.format = Some(OutputFormat::Jpeg { quality })
+                }
+                _ => return Err("the quality only applies to JPEG images".to_string()),
+            }
+        }
+        state.output.format().map(|_| ())
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tile::Tile;
+
+/// Part of the frame to render, at the resolution of the whole frame.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Crop {
+    pub window: CropWindow,
+    /// Pastes the crop into the images already written at the output paths,
+    /// which must have the size of the whole frame, instead of writing the
+    /// crop alone
+    #[serde(default)]
+    pub composite: bool,
+}
+
+/// Left, top, right and bottom edges of a crop, the right and bottom ones
+/// excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CropWindow {
+    /// In pixels from the top left corner.
+    Pixels([usize; 4]),
+    /// As fractions of the width and height of the frame.
+    Normalized([f64; 4]),
+}
+
+impl CropWindow {
+    /// Window given as `left,top,right,bottom`, in pixels when they are all
+    /// integers and as fractions of the frame otherwise.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid crop window {value}");
+        let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+        if parts.len() != 4 {
+            return Err(invalid());
+        }
+        let pixels: Result<Vec<usize>, _> = parts.iter().map(|p| p.parse()).collect();
+        if let Ok(pixels) = pixels {
+            return Ok(CropWindow::Pixels([
+                pixels[0], pixels[1], pixels[2], pixels[3],
+            ]));
+        }
+        let fractions: Vec<f64> = parts
+            .iter()
+            .map(|p| p.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        Ok(CropWindow::Normalized([
+            fractions[0],
+            fractions[1],
+            fractions[2],
+            fractions[3],
+        ]))
+    }
+}
+
+impl Crop {
+    /// Pixels of a frame of `bounds` pixels inside the crop, which must
+    /// hold at least one.
+    pub fn tile(&self, (width, height): (usize, usize)) -> Result<Tile, String> {
+        let [x0, y0, x1, y1] = match self.window {
+            CropWindow::Pixels(edges) => edges,
+            CropWindow::Normalized([x0, y0, x1, y1]) => {
+                // Pixels are in the crop when their center is
+                let edge = |f: f64, size: usize| (f.clamp(0.0, 1.0) * size as f64 - 0.5).ceil();
+                [
+                    edge(x0, width) as usize,
+                    edge(y0, height) as usize,
+                    edge(x1, width) as usize,
+                    edge(y1, height) as usize,
+                ]
+            }
+        };
+        if x0 >= x1 || y0 >= y1 || x1 > width || y1 > height {
+            return Err(format!(
+                "the crop window {x0},{y0} to {x1},{y1} is empty or outside the \
+                 {width}x{height} frame"
+            ));
+        }
+        Ok(Tile {
+            x0,
+            y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        })
+    }
+}
@@ -0,0 +1,196 @@
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aov::Aov,
+    vec3::{
+        functions::{dot, luminance},
+        Color,
+    },
+};
+
+/// Edge avoiding à-trous wavelet denoiser (Dammertz et al.), steered by the
+/// albedo, normal and depth of the first surface and by the variance of the
+/// pixels as in spatiotemporal variance guided filtering (Schied et al.).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Denoiser {
+    /// Passes of the filter, each one reaching twice as far as the previous,
+    /// stopping once they would reach past the image
+    pub iterations: u32,
+    /// Luminance difference, in standard deviations of the noise, above
+    /// which neighbours stop being averaged
+    pub sigma_color: f64,
+    /// Exponent of the cosine between normals, higher values keeping
+    /// sharper creases
+    pub sigma_normal: f64,
+    /// Relative depth difference per pixel of distance tolerated between
+    /// neighbours
+    pub sigma_depth: f64,
+    /// Albedo difference tolerated between neighbours
+    pub sigma_albedo: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.05,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+/// Feature buffers guiding the denoiser, row by row like the image.
+pub struct Features<'a> {
+    pub albedo: &'a [Color],
+    pub normal: &'a [Color],
+    /// Infinite for the background
+    pub depth: &'a [Color],
+    /// Variance of the luminance of each pixel's mean
+    pub variance: &'a [f64],
+}
+
+/// Cubic B-spline weights of the à-trous kernel.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+impl Denoiser {
+    /// Passes the denoiser needs to be rendered.
+    pub const FEATURES: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+    /// Denoised copy of the linear `image`, `width` pixels wide.
+    pub fn denoise(&self, image: &[Color], features: &Features, width: usize) -> Vec<Color> {
+        // Filtering the illumination instead of the color keeps the details
+        // of the albedo, which are free of noise, untouched
+        let albedo: Vec<Color> = features
+            .albedo
+            .iter()
+            .zip(features.depth)
+            .map(|(&a, depth)| {
+                // The background has no albedo to take out
+                if depth.x().is_infinite() {
+                    return Color::new(1.0, 1.0, 1.0);
+                }
+                Color::new(a.x().max(0.01), a.y().max(0.01), a.z().max(0.01))
+            })
+            .collect();
+        let mut pixels: Vec<Pixel> = image
+            .iter()
+            .zip(&albedo)
+            .zip(features.variance)
+            .map(|((&c, &a), &variance)| Pixel {
+                color: Color::new(c.x() / a.x(), c.y() / a.y(), c.z() / a.z()),
+                variance: variance / luminance(a).powi(2),
+            })
+            .collect();
+        // A step reaching past the image leaves every pixel alone
+        let reach = width.max(pixels.len() / width) as isize;
+        let steps = (0..self.iterations).map(|iteration| 1 << iteration);
+        for step in steps.take_while(|&step| step < reach) {
+            let input = pixels.clone();
+            let blurred = blur_variance(&input, width);
+            pixels.par_iter_mut().enumerate().for_each(|(i, pixel)| {
+                *pixel = self.filter_pixel(&input, &blurred, features, width, i, step);
+            });
+        }
+        pixels
+            .iter()
+            .zip(&albedo)
+            .map(|(pixel, &a)| pixel.color * a)
+            .collect()
+    }
+
+    fn filter_pixel(
+        &self,
+        input: &[Pixel],
+        blurred_variance: &[f64],
+        features: &Features,
+        width: usize,
+        i: usize,
+        step: isize,
+    ) -> Pixel {
+        let height = input.len() / width;
+        let (x, y) = ((i % width) as isize, (i / width) as isize);
+        let center = input[i];
+        let l_p = luminance(center.color);
+        let color_scale = self.sigma_color * blurred_variance[i].max(0.0).sqrt() + 1e-6;
+
+        let mut sum = Pixel::default();
+        let mut weight_sum = 0.0;
+        for (ky, hy) in KERNEL.iter().enumerate() {
+            for (kx, hx) in KERNEL.iter().enumerate() {
+                let qx = x + (kx as isize - 2) * step;
+                let qy = y + (ky as isize - 2) * step;
+                if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                    continue;
+                }
+                let j = qy as usize * width + qx as usize;
+                let q = input[j];
+
+                let w_color = (-(l_p - luminance(q.color)).abs() / color_scale).exp();
+                let w = hx * hy * w_color * self.feature_weight(features, i, j, step);
+                sum.color += w * q.color;
+                sum.variance += w * w * q.variance;
+                weight_sum += w;
+            }
+        }
+
+        // The center always weighs in, so the sum is positive
+        Pixel {
+            color: sum.color / weight_sum,
+            variance: sum.variance / (weight_sum * weight_sum),
+        }
+    }
+
+    /// Similarity of the surfaces seen by pixels `i` and `j`, `step` pixels apart.
+    fn feature_weight(&self, features: &Features, i: usize, j: usize, step: isize) -> f64 {
+        let (z_p, z_q) = (features.depth[i].x(), features.depth[j].x());
+        match (z_p.is_infinite(), z_q.is_infinite()) {
+            (true, true) => return 1.0,
+            (true, false) | (false, true) => return 0.0,
+            _ => {}
+        }
+
+        // Filtered normals are shorter than unit along edges
+        let (n_p, n_q) = (features.normal[i], features.normal[j]);
+        let cos = dot(n_p, n_q) / (n_p.norm_squared() * n_q.norm_squared()).sqrt().max(1e-12);
+        let w_normal = cos.max(0.0).powf(self.sigma_normal);
+        let w_depth = (-(z_p - z_q).abs() / (self.sigma_depth * step as f64 * z_p + 1e-6)).exp();
+        let albedo_diff = features.albedo[i] - features.albedo[j];
+        let w_albedo =
+            (-albedo_diff.norm_squared() / (self.sigma_albedo * self.sigma_albedo)).exp();
+        w_normal * w_depth * w_albedo
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Pixel {
+    color: Color,
+    variance: f64,
+}
+
+/// Variance smoothed over 3x3 pixels, steadier than the raw estimates for
+/// steering the filter.
+fn blur_variance(pixels: &[Pixel], width: usize) -> Vec<f64> {
+    let height = pixels.len() / width;
+    let weights = [0.25, 0.5, 0.25];
+    (0..pixels.len())
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+            for (dy, wy) in weights.iter().enumerate() {
+                for (dx, wx) in weights.iter().enumerate() {
+                    let (qx, qy) = ((x + dx).wrapping_sub(1), (y + dy).wrapping_sub(1));
+                    if qx < width && qy < height {
+                        sum += wx * wy * pixels[qy * width + qx].variance;
+                        weight_sum += wx * wy;
+                    }
+                }
+            }
+            sum / weight_sum
+        })
+        .collect()
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adaptive::PixelStats,
+    aov::AovFilm,
+    film::Film,
+    renderer::{
+        progress_bar, sample_counts, sample_region, sampled_region, show_progress, Accumulation,
+    },
+    state::State,
+    tile::{Tile, Tiles},
+};
+
+/// Settings of a distributed render, whose tiles are rendered by worker
+/// processes on this machine or others.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Distributed {
+    /// Addresses the workers listen on, such as `render1:7878`
+    pub workers: Vec<String>,
+    /// Side in pixels of the tiles handed to the workers, which split them
+    /// in turn into the `tiles` of the state
+    pub job_size: usize,
+    /// Seconds to wait for a worker to render a tile or take a message,
+    /// after which its tile goes to the other workers
+    pub timeout: u64,
+}
+
+impl Default for Distributed {
+    fn default() -> Self {
+        Self {
+            workers: Vec::new(),
+            job_size: 128,
+            timeout: 300,
+        }
+    }
+}
+
+/// Version of the messages exchanged with the workers.
+///
+/// Every message is a line of space separated words ending with the length
+/// of a binary payload, which follows the line. The coordinator sends
+/// `STATE <version>` with the state as JSON, which the worker answers with
+/// `READY`, then `TILE <x0> <y0> <width> <height>` for every tile, answered
+/// with `FILM` and the film, passes and statistics of the tile as little
+/// endian doubles. Either side sends `ERROR` with a message instead when
+/// something goes wrong, and the coordinator closes the connection when
+/// every tile is done.
+const PROTOCOL_VERSION: &str = "1";
+
+/// Largest payload accepted, well above the state and film of a tile.
+const MAX_PAYLOAD: usize = 1 << 30;
+
+/// Path traces the image of `state` on the workers, merging the tiles they
+/// send back as they arrive.
+pub fn render(state: &State, settings: &Distributed) -> Accumulation {
+    let region = sampled_region(state);
+    let jobs = Tiles {
+        size: settings.job_size,
+        order: state.tiles.order,
+    }
+    .split_region(&region);
+    let json = serde_json::to_string(state).expect("error serializing the state");
+
+    let queue = Mutex::new((0..jobs.len()).collect::<VecDeque<_>>());
+    let remaining = AtomicUsize::new(jobs.len());
+    let (sender, receiver) = mpsc::channel();
+    let mut accumulation = Accumulation::new(state);
+    let image_width = state.width.unwrap() as usize;
+
+    let pb = progress_bar(region.len() as u64);
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for address in &settings.workers {
+            let sender = sender.clone();
+            let coordinator = Coordinator {
+                state,
+                json: &json,
+                timeout: Duration::from_secs(settings.timeout.max(1)),
+                jobs: &jobs,
+                queue: &queue,
+                remaining: &remaining,
+            };
+            scope.spawn(move || {
+                if let Err(e) = coordinator.work(address, sender) {
+                    eprintln!("worker {address} failed: {e}");
+                }
+            });
+        }
+        drop(sender);
+
+        // Tiles are merged in their order, so that the image does not depend
+        // on which worker rendered which tile
+        let mut pending: Vec<Option<TileResult>> = jobs.iter().map(|_| None).collect();
+        let mut next = 0;
+        for (job, result) in receiver {
+            pb.inc(jobs[job].len() as u64);
+            show_progress(&pb, start, "");
+            pending[job] = Some(result);
+            while let Some(result) = pending.get_mut(next).and_then(Option::take) {
+                accumulation.film.merge(&result.film);
+                accumulation.aovs.merge(&result.aovs);
+                jobs[next].paste(&mut accumulation.stats, &result.stats, image_width, 1);
+                next += 1;
+            }
+        }
+        // Every worker has stopped
+        if next < jobs.len() {
+            panic!(
+                "error rendering on the workers: {} tiles were not rendered",
+                jobs.len() - next
+            );
+        }
+    });
+    pb.finish_with_message("[Render Complete!]");
+
+    accumulation
+}
+
+/// Film, passes and statistics of the pixels of a tile.
+struct TileResult {
+    film: Film,
+    aovs: AovFilm,
+    stats: Vec<PixelStats>,
+}
+
+/// What the threads talking to the workers share.
+#[derive(Clone, Copy)]
+struct Coordinator<'a> {
+    state: &'a State,
+    json: &'a str,
+    /// Longest wait on a read or write of the worker
+    timeout: Duration,
+    jobs: &'a [Tile],
+    /// Tiles left to hand out, to which the tiles of a failed worker return
+    queue: &'a Mutex<VecDeque<usize>>,
+    /// Tiles not rendered yet
+    remaining: &'a AtomicUsize,
+}
+
+impl Coordinator<'_> {
+    /// Hands tiles to the worker at `address` until all of them are rendered,
+    /// sending the results to `results`.
+    fn work(&self, address: &str, results: Sender<(usize, TileResult)>) -> io::Result<()> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        // A stalled worker fails like a disconnected one
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        send(
+            &mut writer,
+            &format!("STATE {PROTOCOL_VERSION}"),
+            self.json.as_bytes(),
+        )?;
+        expect(&mut reader, "READY")?;
+
+        while self.remaining.load(Ordering::SeqCst) > 0 {
+            let job = self.queue.lock().unwrap().pop_front();
+            let Some(job) = job else {
+                // Tiles of a worker failing now would come back to the queue
+                thread::sleep(Duration::from_millis(50));
+                continue;
+            };
+            let tile = &self.jobs[job];
+            let result = send(
+                &mut writer,
+                &format!(
+                    "TILE {} {} {} {}",
+                    tile.x0, tile.y0, tile.width, tile.height
+                ),
+                &[],
+            )
+            .and_then(|()| expect(&mut reader, "FILM"))
+            .and_then(|data| decode_tile(self.state, tile, &data));
+            match result {
+                Ok(result) => {
+                    self.remaining.fetch_sub(1, Ordering::SeqCst);
+                    // The coordinator only stops listening once every tile is done
+                    let _ = results.send((job, result));
+                }
+                Err(e) => {
+                    self.queue.lock().unwrap().push_back(job);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serves the coordinators connecting to `address`, one at a time, until the
+/// process is stopped.
+pub fn serve(address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("Worker listening on {}", listener.local_addr()?);
+    listen(listener)
+}
+
+/// Serves the coordinators connecting to `listener`, one at a time.
+pub fn listen(listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("error accepting a connection: {e}");
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |a| a.to_string());
+        println!("Rendering for {peer}");
+        match session(stream) {
+            Ok(tiles) => println!("Rendered {tiles} tiles for {peer}"),
+            Err(e) => eprintln!("error rendering for {peer}: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Renders the tiles a coordinator asks for, returning how many.
+fn session(stream: TcpStream) -> io::Result<usize> {
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let (words, json) = receive(&mut reader)?;
+    let state = match words.as_slice() {
+        [kind, version] if kind == "STATE" && version == PROTOCOL_VERSION => {
+            String::from_utf8(json)
+                .map_err(|e| e.to_string())
+                .and_then(|json| State::from_json_str(&json).map_err(|e| e.to_string()))
+        }
+        _ => Err(format!("expected a state of version {PROTOCOL_VERSION}")),
+    };
+    let state = match state {
+        Ok(state) => state,
+        Err(e) => {
+            send(&mut writer, "ERROR", e.as_bytes())?;
+            return Err(invalid(e));
+        }
+    };
+    send(&mut writer, "READY", &[])?;
+
+    let frame = (state.width.unwrap() as usize, state.height as usize);
+    let mut rendered = 0;
+    loop {
+        let words = match receive(&mut reader) {
+            Ok((words, _)) => words,
+            // The coordinator has no more tiles
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(rendered),
+            Err(e) => return Err(e),
+        };
+        let tile = match parse_tile(&words, frame) {
+            Ok(tile) => tile,
+            Err(e) => {
+                send(&mut writer, "ERROR", e.as_bytes())?;
+                return Err(invalid(e));
+            }
+        };
+        send(&mut writer, "FILM", &render_tile(&state, &tile))?;
+        rendered += 1;
+    }
+}
+
+fn parse_tile(words: &[String], (width, height): (usize, usize)) -> Result<Tile, String> {
+    let numbers: Vec<usize> = match words {
+        [kind, rest @ ..] if kind == "TILE" && rest.len() == 4 => {
+            rest.iter().filter_map(|w| w.parse().ok()).collect()
+        }
+        _ => Vec::new(),
+    };
+    match numbers[..] {
+        [x0, y0, w, h]
+            if w > 0
+                && h > 0
+                && x0.checked_add(w).is_some_and(|x1| x1 <= width)
+                && y0.checked_add(h).is_some_and(|y1| y1 <= height) =>
+        {
+            Ok(Tile {
+                x0,
+                y0,
+                width: w,
+                height: h,
+            })
+        }
+        _ => Err(format!("invalid tile request: {}", words.join(" "))),
+    }
+}
+
+/// Film, passes and statistics of `tile`, rendered from scratch, as sent to
+/// the coordinator.
+fn render_tile(state: &State, tile: &Tile) -> Vec<u8> {
+    let frame = (state.width.unwrap() as usize, state.height as usize);
+    let mut film = Film::for_tile(frame, tile, &state.filter);
+    let mut aovs = AovFilm::for_tile(&state.aov_passes(), frame, tile, &state.filter);
+    let mut stats = vec![PixelStats::default(); tile.len()];
+    let until = sample_counts(state).1;
+    sample_region(
+        state,
+        tile,
+        &mut film,
+        &mut aovs,
+        &mut stats,
+        until,
+        &|_| {},
+    );
+
+    let stats = stats.iter().flat_map(|s| s.raw());
+    film.raw()
+        .into_iter()
+        .chain(aovs.raw())
+        .chain(stats)
+        .flat_map(f64::to_le_bytes)
+        .collect()
+}
+
+/// Reads back what `render_tile` sent.
+fn decode_tile(state: &State, tile: &Tile, data: &[u8]) -> io::Result<TileResult> {
+    let frame = (state.width.unwrap() as usize, state.height as usize);
+    let passes = state.aov_passes();
+    let (columns, rows) = Film::reached(frame, tile, &state.filter);
+    let window = columns.len() * rows.len();
+    let (film_len, aovs_len) = (4 * window, 4 * window * passes.len());
+    if data.len() != 8 * (film_len + aovs_len + 3 * tile.len()) {
+        return Err(invalid(format!(
+            "the film of tile {},{} has the wrong size",
+            tile.x0, tile.y0
+        )));
+    }
+
+    let values: Vec<f64> = data
+        .chunks_exact(8)
+        .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    let mut film = Film::for_tile(frame, tile, &state.filter);
+    film.load_raw(&values[..film_len]);
+    let mut aovs = AovFilm::for_tile(&passes, frame, tile, &state.filter);
+    aovs.load_raw(&values[film_len..film_len + aovs_len]);
+    let stats = values[film_len + aovs_len..]
+        .chunks_exact(3)
+        .map(|s| PixelStats::from_raw([s[0], s[1], s[2]]))
+        .collect();
+    Ok(TileResult { film, aovs, stats })
+}
+
+fn send(writer: &mut impl Write, header: &str, payload: &[u8]) -> io::Result<()> {
+    writeln!(writer, "{header} {}", payload.len())?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Words of the next message, without the length, and its payload.
+fn receive(reader: &mut impl BufRead) -> io::Result<(Vec<String>, Vec<u8>)> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let mut words: Vec<String> = line.split_whitespace().map(String::from).collect();
+    let len = words
+        .pop()
+        .and_then(|len| len.parse().ok())
+        .filter(|&len| len <= MAX_PAYLOAD)
+        .ok_or_else(|| invalid(format!("invalid message: {}", line.trim_end())))?;
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok((words, payload))
+}
+
+/// Payload of the next message, which must be of the given `kind`.
+fn expect(reader: &mut impl BufRead, kind: &str) -> io::Result<Vec<u8>> {
+    let (words, payload) = receive(reader)?;
+    match words.first().map(String::as_str) {
+        Some(k) if k == kind => Ok(payload),
+        Some("ERROR") => Err(io::Error::other(String::from_utf8_lossy(&payload))),
+        _ => Err(invalid(format!("expected {kind}, got {}", words.join(" ")))),
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
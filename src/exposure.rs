@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{utils::deg_to_rad, vec3::Color};
+
+/// Photographic exposure settings, reading scene radiance as luminance in
+/// cd/m². The f-stop also sets the aperture of the lens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Exposure {
+    pub iso: f64,
+    /// Time the sensor is exposed, in seconds
+    pub shutter_speed: f64,
+    pub f_stop: f64,
+    /// Color temperature, in kelvin, of the light that should look neutral
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub white_balance: Option<f64>,
+    /// Height of the sensor in scene units, which with `vfov` gives the focal length
+    pub sensor_height: f64,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            iso: 100.0,
+            shutter_speed: 1.0 / 125.0,
+            f_stop: 8.0,
+            white_balance: None,
+            sensor_height: 0.024,
+        }
+    }
+}
+
+pub type Matrix3 = [[f64; 3]; 3];
+
+pub const IDENTITY: Matrix3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+const SRGB_TO_XYZ: Matrix3 = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+
+const XYZ_TO_SRGB: Matrix3 = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+const BRADFORD: Matrix3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+const BRADFORD_INVERSE: Matrix3 = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867],
+];
+
+/// White point of sRGB in XYZ.
+const D65: [f64; 3] = [0.95047, 1.0, 1.08883];
+
+impl Exposure {
+    /// Exposure value at ISO 100 of the settings.
+    pub fn ev100(&self) -> f64 {
+        (self.f_stop * self.f_stop / self.shutter_speed).log2() - (self.iso / 100.0).log2()
+    }
+
+    /// Factor turning luminance into film values, so that 1 is the saturation
+    /// of the sensor (Lagarde and de Rousiers).
+    pub fn scale(&self) -> f64 {
+        1.0 / (1.2 * 2f64.powf(self.ev100()))
+    }
+
+    /// Diameter of the lens opening for a camera with the given vertical field of view.
+    pub fn aperture(&self, vfov: f64) -> f64 {
+        let focal_length = self.sensor_height / (2.0 * (deg_to_rad(vfov) / 2.0).tan());
+        focal_length / self.f_stop
+    }
+
+    /// Linear sRGB transform applying the white balance and the exposure scale.
+    pub fn film_matrix(&self) -> Matrix3 {
+        let balance = match self.white_balance {
+            Some(temperature) => white_balance(temperature),
+            None => IDENTITY,
+        };
+        let scale = self.scale();
+        balance.map(|row| row.map(|x| x * scale))
+    }
+}
+
+/// Von Kries adaptation in the Bradford cone space, from the white of a
+/// black body at `temperature` kelvin to the D65 white of sRGB.
+pub fn white_balance(temperature: f64) -> Matrix3 {
+    let (x, y) = planckian_chromaticity(temperature);
+    let source = [x / y, 1.0, (1.0 - x - y) / y];
+
+    let source_cone = apply(&BRADFORD, source);
+    let target_cone = apply(&BRADFORD, D65);
+    let mut gains = [[0.0; 3]; 3];
+    for i in 0..3 {
+        gains[i][i] = target_cone[i] / source_cone[i];
+    }
+
+    let adaptation = mul(&BRADFORD_INVERSE, &mul(&gains, &BRADFORD));
+    mul(&XYZ_TO_SRGB, &mul(&adaptation, &SRGB_TO_XYZ))
+}
+
+/// CIE xy chromaticity of a black body, from the cubic fits of Kim et al.
+fn planckian_chromaticity(temperature: f64) -> (f64, f64) {
+    let t = temperature.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+
+    (x, y)
+}
+
+fn mul(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn apply(m: &Matrix3, v: [f64; 3]) -> [f64; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+pub fn transform(m: &Matrix3, color: Color) -> Color {
+    let [r, g, b] = apply(m, [color.x(), color.y(), color.z()]);
+    Color::new(r, g, b)
+}
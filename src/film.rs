@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+use crate::{filter::Filter, tile::Tile, vec3::Color};
+
+/// Filtered samples accumulated over a window of the image.
+///
+/// Every sample is splatted on all the pixels its filter reaches, so windows
+/// rendered separately overlap and are added together with `merge`.
+#[derive(Debug, Clone)]
+pub struct Film {
+    /// First column of the image covered by the film
+    x0: usize,
+    /// First row of the image covered by the film
+    y0: usize,
+    width: usize,
+    rows: usize,
+    pixels: Vec<FilmPixel>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FilmPixel {
+    sum: Color,
+    weight: f64,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::band(width, 0, height)
+    }
+
+    /// Film covering `rows` rows of the image starting at `y0`.
+    pub fn band(width: usize, y0: usize, rows: usize) -> Self {
+        Self::window(0, y0, width, rows)
+    }
+
+    /// Film covering `width` columns from `x0` of `rows` rows from `y0`.
+    pub fn window(x0: usize, y0: usize, width: usize, rows: usize) -> Self {
+        Self {
+            x0,
+            y0,
+            width,
+            rows,
+            pixels: vec![FilmPixel::default(); width * rows],
+        }
+    }
+
+    /// Band receiving the samples of image row `y` through `filter`, clipped
+    /// to an image `height` rows tall.
+    pub fn for_row(width: usize, height: usize, y: usize, filter: &Filter) -> Self {
+        Self::for_tile((width, height), &Tile::row(width, y), filter)
+    }
+
+    /// Window receiving the samples of `tile` through `filter`, clipped to an
+    /// image of `bounds` pixels.
+    pub fn for_tile(bounds: (usize, usize), tile: &Tile, filter: &Filter) -> Self {
+        let (columns, rows) = Self::reached(bounds, tile, filter);
+        Self::window(columns.start, rows.start, columns.len(), rows.len())
+    }
+
+    /// Columns and rows of an image of `bounds` pixels reached by the samples
+    /// of `tile`.
+    pub fn reached(
+        (width, height): (usize, usize),
+        tile: &Tile,
+        filter: &Filter,
+    ) -> (Range<usize>, Range<usize>) {
+        let reach = (filter.radius() + 0.5).ceil() as usize;
+        (
+            tile.x0.saturating_sub(reach)..(tile.x0 + tile.width + reach).min(width),
+            tile.y0.saturating_sub(reach)..(tile.y0 + tile.height + reach).min(height),
+        )
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn rows(&self) -> Range<usize> {
+        self.y0..self.y0 + self.rows
+    }
+
+    /// Adds `color` seen at the image position (`x`, `y`), in pixels from
+    /// the top left corner, to the pixels whose center is within the filter
+    /// radius.
+    pub fn splat(&mut self, (x, y): (f64, f64), color: Color, filter: &Filter) {
+        let radius = filter.radius();
+        // Half open ranges, so that a box filter of radius 0.5 hits one pixel
+        let first = |c: f64| ((c - 0.5 - radius).floor() + 1.0).max(0.0) as usize;
+        let last = |c: f64| (c - 0.5 + radius).floor();
+
+        let x_last = last(x).min((self.x0 + self.width) as f64 - 1.0);
+        let y_last = last(y).min((self.y0 + self.rows) as f64 - 1.0);
+        if x_last < 0.0 || y_last < 0.0 {
+            return;
+        }
+        for py in first(y).max(self.y0)..=y_last as usize {
+            for px in first(x).max(self.x0)..=x_last as usize {
+                let weight = filter.evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                if weight != 0.0 {
+                    let pixel = &mut self.pixels[(py - self.y0) * self.width + px - self.x0];
+                    pixel.sum += weight * color;
+                    pixel.weight += weight;
+                }
+            }
+        }
+    }
+
+    /// Adds the samples of `other`, which must lie within this film.
+    pub fn merge(&mut self, other: &Film) {
+        for (row, others) in other.pixels.chunks_exact(other.width.max(1)).enumerate() {
+            let offset = (other.y0 - self.y0 + row) * self.width + other.x0 - self.x0;
+            for (pixel, o) in self.pixels[offset..].iter_mut().zip(others) {
+                pixel.sum += o.sum;
+                pixel.weight += o.weight;
+            }
+        }
+    }
+
+    /// Weighted sum of the samples and sum of the weights of every pixel,
+    /// four numbers each, which `load_raw` restores.
+    pub fn raw(&self) -> Vec<f64> {
+        self.pixels
+            .iter()
+            .flat_map(|p| [p.sum.x(), p.sum.y(), p.sum.z(), p.weight])
+            .collect()
+    }
+
+    /// Replaces the samples with the ones saved by `raw` from a film of the
+    /// same size.
+    pub fn load_raw(&mut self, raw: &[f64]) {
+        for (pixel, r) in self.pixels.iter_mut().zip(raw.chunks_exact(4)) {
+            pixel.sum = Color::new(r[0], r[1], r[2]);
+            pixel.weight = r[3];
+        }
+    }
+
+    /// Weighted average of the samples of every pixel, row by row. Negative
+    /// lobes of the filters can leave pixels slightly below zero, which are
+    /// clamped.
+    pub fn resolve(&self) -> Vec<Color> {
+        self.resolve_signed()
+            .into_iter()
+            .map(|c| Color::new(c.x().max(0.0), c.y().max(0.0), c.z().max(0.0)))
+            .collect()
+    }
+
+    /// Weighted average of the samples of every pixel, row by row, for
+    /// values which can be negative.
+    pub fn resolve_signed(&self) -> Vec<Color> {
+        self.pixels
+            .iter()
+            .map(|p| {
+                if p.weight <= 0.0 {
+                    return Color::default();
+                }
+                p.sum / p.weight
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,151 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// Reconstruction filter weighting the samples splatted on the pixels around
+/// them. Radii are in pixels; wider filters are smoother but blurrier.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Filter {
+    /// Plain average of the samples falling inside each pixel.
+    Box {
+        #[serde(default = "default_box_radius")]
+        radius: f64,
+    },
+    /// Linear falloff, also known as triangle filter.
+    Tent {
+        #[serde(default = "default_tent_radius")]
+        radius: f64,
+    },
+    /// Gaussian of standard deviation `sigma`, shifted to reach zero at `radius`.
+    Gaussian {
+        #[serde(default = "default_gaussian_radius")]
+        radius: f64,
+        #[serde(default = "default_sigma")]
+        sigma: f64,
+    },
+    /// Mitchell-Netravali cubic, sharpening with small negative lobes.
+    Mitchell {
+        #[serde(default = "default_mitchell_radius")]
+        radius: f64,
+        #[serde(default = "default_mitchell_parameter")]
+        b: f64,
+        #[serde(default = "default_mitchell_parameter")]
+        c: f64,
+    },
+    /// Sinc windowed by a wider sinc, the sharpest and most prone to ringing.
+    Lanczos {
+        #[serde(default = "default_lanczos_radius")]
+        radius: f64,
+        #[serde(default = "default_tau")]
+        tau: f64,
+    },
+}
+
+fn default_box_radius() -> f64 {
+    0.5
+}
+
+fn default_tent_radius() -> f64 {
+    1.0
+}
+
+fn default_gaussian_radius() -> f64 {
+    1.5
+}
+
+fn default_sigma() -> f64 {
+    0.5
+}
+
+fn default_mitchell_radius() -> f64 {
+    2.0
+}
+
+fn default_mitchell_parameter() -> f64 {
+    1.0 / 3.0
+}
+
+fn default_lanczos_radius() -> f64 {
+    3.0
+}
+
+fn default_tau() -> f64 {
+    3.0
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box {
+            radius: default_box_radius(),
+        }
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius,
+        }
+    }
+
+    /// Weight of a sample at offset (`x`, `y`) from the center of a pixel.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        match *self {
+            Filter::Box { radius } => {
+                if x <= radius {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Tent { radius } => (radius - x).max(0.0),
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                if x >= radius {
+                    return 0.0;
+                }
+                mitchell(2.0 * x / radius, b, c)
+            }
+            Filter::Lanczos { radius, tau } => {
+                if x >= radius {
+                    return 0.0;
+                }
+                sinc(x) * sinc(x / tau)
+            }
+        }
+    }
+}
+
+/// Mitchell-Netravali cubic over [0, 2].
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let (x2, x3) = (x * x, x * x * x);
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b))
+            / 6.0
+    } else {
+        ((-b - 6.0 * c) * x3
+            + (6.0 * b + 30.0 * c) * x2
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
@@ -0,0 +1,123 @@
+use crate::{
+    ray::Ray,
+    vec3::{Point3, Vec3},
+};
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Default for Aabb {
+    /// The empty box, which leaves any box it is merged with unchanged.
+    fn default() -> Self {
+        Self {
+            min: Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+}
+
+impl Aabb {
+    pub fn new(a: Point3, b: Point3) -> Self {
+        Self {
+            min: Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    pub fn point(p: Point3) -> Self {
+        Self { min: p, max: p }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Point3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        }
+    }
+
+    pub fn union_point(&self, p: Point3) -> Self {
+        self.union(&Aabb::point(p))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.diagonal();
+        2.0 * (d.x() * d.y() + d.x() * d.z() + d.y() * d.z())
+    }
+
+    /// Axis along which the box is the longest.
+    pub fn max_extent(&self) -> usize {
+        let d = self.diagonal();
+        if d.x() > d.y() && d.x() > d.z() {
+            0
+        } else if d.y() > d.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Relative position of `p` inside the box, 0 at `min` and 1 at `max`.
+    pub fn offset(&self, p: Point3) -> Vec3 {
+        let mut o = p - self.min;
+        for axis in 0..3 {
+            if self.max[axis] > self.min[axis] {
+                o[axis] /= self.max[axis] - self.min[axis];
+            }
+        }
+        o
+    }
+
+    /// Whether `ray` crosses the box between `t_min` and `t_max`.
+    pub fn hit(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+        for axis in 0..3 {
+            let inv = 1.0 / ray.dir()[axis];
+            let mut t0 = (self.min[axis] - ray.origin()[axis]) * inv;
+            let mut t1 = (self.max[axis] - ray.origin()[axis]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaNs from a ray lying in a face leave the bounds unchanged
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Center and radius of a sphere enclosing the box.
+    pub fn bounding_sphere(&self) -> (Point3, f64) {
+        let center = self.centroid();
+        let radius = if self.is_empty() {
+            0.0
+        } else {
+            (self.max - center).norm()
+        };
+        (center, radius)
+    }
+}
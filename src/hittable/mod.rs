@@ -1,3 +1,4 @@
+pub mod aabb;
 pub mod sphere;
 
 // use std::{cell::RefCell, rc::Rc};
@@ -16,6 +17,7 @@ pub struct HitRecord {
     pub normal: Vec3,
     pub material: Material,
     pub t: f64,
+    pub time: f64,
     pub front_face: bool,
 }
 
@@ -32,14 +34,24 @@ pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
 }
 
-pub fn hit_world(world: &Vec<Sphere>, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+pub fn hit_world(world: &[Sphere], r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    hit_world_index(world, r, t_min, t_max).map(|(_, hit_record)| hit_record)
+}
+
+/// Closest hit along `r` and the index of the sphere it belongs to.
+pub fn hit_world_index(
+    world: &[Sphere],
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(usize, HitRecord)> {
     let mut closest_so_far = t_max;
     let mut hit_record = None;
 
-    for obj in world.iter() {
+    for (index, obj) in world.iter().enumerate() {
         if let Some(hit) = obj.hit(r, t_min, closest_so_far) {
             closest_so_far = hit.t;
-            hit_record = Some(hit);
+            hit_record = Some((index, hit));
         }
     }
 
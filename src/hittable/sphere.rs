@@ -4,7 +4,9 @@ use super::HitRecord;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::functions::dot;
-use crate::vec3::Point3;
+use crate::vec3::{Point3, Vec3};
+
+use super::aabb::Aabb;
 
 use super::Hittable;
 
@@ -13,6 +15,9 @@ pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
     pub material: Material,
+    /// Where the center moves to by the end of the frame, for motion blur
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub center_end: Option<Point3>,
 }
 
 impl Sphere {
@@ -21,13 +26,47 @@ impl Sphere {
             center,
             radius,
             material,
+            center_end: None,
+        }
+    }
+
+    /// Sphere moving linearly from `center` at time 0 to `center_end` at time 1.
+    pub fn moving(center: Point3, center_end: Point3, radius: f64, material: Material) -> Self {
+        Self {
+            center,
+            radius,
+            material,
+            center_end: Some(center_end),
+        }
+    }
+
+    pub fn center_at(&self, time: f64) -> Point3 {
+        match self.center_end {
+            Some(end) => self.center + time * (end - self.center),
+            None => self.center,
+        }
+    }
+
+    /// The still sphere occupying the place of this one at `time`.
+    pub fn at_time(&self, time: f64) -> Self {
+        Self::new(self.center_at(time), self.radius, self.material)
+    }
+
+    /// Box enclosing the sphere over the whole frame.
+    pub fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let start = Aabb::new(self.center - r, self.center + r);
+        match self.center_end {
+            Some(end) => start.union(&Aabb::new(end - r, end + r)),
+            None => start,
         }
     }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin() - self.center;
+        let center = self.center_at(r.time());
+        let oc = r.origin() - center;
         let a = r.dir().norm_squared();
         let half_b = dot(oc, r.dir());
         let c = oc.norm_squared() - self.radius * self.radius;
@@ -39,13 +78,14 @@ impl Hittable for Sphere {
             for root in [root_a, root_b].iter() {
                 if *root < t_max && *root > t_min {
                     let p = r.at(*root);
-                    let normal = (p - self.center) / self.radius;
+                    let normal = (p - center) / self.radius;
                     let front_face = dot(r.dir(), normal) < 0.0;
 
-                    let (_u, _v) = u_v_from_sphere_hit_point(p - self.center);
+                    let (_u, _v) = u_v_from_sphere_hit_point(p - center);
 
                     return Some(HitRecord {
                         t: *root,
+                        time: r.time(),
                         p,
                         normal: if front_face { normal } else { -normal },
                         front_face,
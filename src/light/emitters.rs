@@ -0,0 +1,113 @@
+use crate::{hittable::aabb::Aabb, ray::Ray, vec3::Color};
+
+use super::{Illuminate, LightSource};
+
+#[derive(Debug, Clone, Copy)]
+enum EmitterNodeKind {
+    /// Index of the light
+    Leaf(usize),
+    /// Index of the second child, the first one following the node
+    Interior(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EmitterNode {
+    bounds: Aabb,
+    kind: EmitterNodeKind,
+}
+
+/// Bounding volume hierarchy over the quad and disk lights, which paths can
+/// hit without being geometry. Emissive spheres are found as geometry, and
+/// the other lights cannot be hit.
+#[derive(Debug, Clone, Default)]
+pub struct EmitterBvh {
+    nodes: Vec<EmitterNode>,
+}
+
+impl EmitterBvh {
+    pub fn new(lights: &[LightSource]) -> Self {
+        let mut entries: Vec<(usize, Aabb)> = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, light)| match light {
+                LightSource::Quad(l) => Some((i, l.bounding_box())),
+                LightSource::Disk(l) => Some((i, l.bounding_box())),
+                _ => None,
+            })
+            .collect();
+
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * entries.len()),
+        };
+        if !entries.is_empty() {
+            bvh.build(&mut entries);
+        }
+        bvh
+    }
+
+    /// Appends the subtree over `entries`, split at the median of their
+    /// centroids along the longest axis.
+    fn build(&mut self, entries: &mut [(usize, Aabb)]) {
+        if let [(light, bounds)] = entries {
+            self.nodes.push(EmitterNode {
+                bounds: *bounds,
+                kind: EmitterNodeKind::Leaf(*light),
+            });
+            return;
+        }
+
+        let bounds = entries
+            .iter()
+            .fold(Aabb::default(), |acc, (_, b)| acc.union(b));
+        let centroids = entries
+            .iter()
+            .fold(Aabb::default(), |acc, (_, b)| acc.union_point(b.centroid()));
+        let axis = centroids.max_extent();
+        let mid = entries.len() / 2;
+        let centroid = |b: &Aabb| b.centroid()[axis];
+        entries.select_nth_unstable_by(mid, |a, b| centroid(&a.1).total_cmp(&centroid(&b.1)));
+
+        let node = self.nodes.len();
+        self.nodes.push(EmitterNode {
+            bounds,
+            kind: EmitterNodeKind::Interior(0),
+        });
+        let (first, second) = entries.split_at_mut(mid);
+        self.build(first);
+        self.nodes[node].kind = EmitterNodeKind::Interior(self.nodes.len());
+        self.build(second);
+    }
+
+    /// Distance along `ray` and radiance of the closest of the `lights` it
+    /// hits before `t_max`.
+    pub fn hit(&self, lights: &[LightSource], ray: &Ray, t_max: f64) -> Option<(f64, Color)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut closest: Option<(f64, Color)> = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let t_closest = closest.map_or(t_max, |(t, _)| t);
+            if !node.bounds.hit(ray, 0.0, t_closest) {
+                continue;
+            }
+            match node.kind {
+                EmitterNodeKind::Leaf(light) => {
+                    if let Some(hit) = lights[light].emitted(ray, t_closest) {
+                        closest = Some(hit);
+                    }
+                }
+                EmitterNodeKind::Interior(second) => {
+                    stack.push(second);
+                    stack.push(index + 1);
+                }
+            }
+        }
+        closest
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
@@ -0,0 +1,393 @@
+pub mod emitters;
+pub mod selection;
+
+use std::f64::consts::PI;
+
+use palette::Srgb;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    hittable::{aabb::Aabb, sphere::Sphere},
+    material::SrgbAsArray,
+    ray::Ray,
+    sampler::Sampler,
+    utils::deg_to_rad,
+    vec3::{
+        functions::{cross, dot, orthonormal_basis, unit_vec},
+        Color, Point3, Vec3,
+    },
+};
+
+/// Light emitters sampled by the direct lighting code.
+///
+/// Powers are radiant fluxes in watts, the directional light takes its
+/// irradiance in W/m² and emissive spheres emit the radiance of their material.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum LightSource {
+    Sphere(Sphere),
+    Point(PointLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight),
+    Quad(QuadLight),
+    Disk(DiskLight),
+}
+
+/// Entry of the `lights` array, which used to only hold bare emissive spheres.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LightEntry {
+    Source(LightSource),
+    Sphere(Sphere),
+}
+
+pub fn deserialize_lights<'de, D>(deserializer: D) -> Result<Option<Vec<LightSource>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries: Option<Vec<LightEntry>> = Option::deserialize(deserializer)?;
+    Ok(entries.map(|entries| {
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                LightEntry::Source(light) => light,
+                LightEntry::Sphere(sphere) => LightSource::Sphere(sphere),
+            })
+            .collect()
+    }))
+}
+
+/// A direction towards a light together with the radiance arriving from it,
+/// already divided by the probability of having sampled that direction.
+pub struct LightSample {
+    pub dir: Vec3,
+    pub distance: f64,
+    pub radiance: Color,
+}
+
+pub trait Illuminate {
+    fn sample(&self, p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample>;
+
+    /// Radiance seen along `ray` when it reaches the emitter before `t_max`.
+    fn emitted(&self, _ray: &Ray, _t_max: f64) -> Option<(f64, Color)> {
+        None
+    }
+}
+
+impl LightSource {
+    /// The light as it is at `time`, with moving emitters frozen in place.
+    pub fn at_time(&self, time: f64) -> Self {
+        match self {
+            LightSource::Sphere(s) => LightSource::Sphere(s.at_time(time)),
+            _ => *self,
+        }
+    }
+}
+
+impl Illuminate for LightSource {
+    fn sample(&self, p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        match self {
+            LightSource::Sphere(s) => s.sample(p, sampler),
+            LightSource::Point(l) => l.sample(p, sampler),
+            LightSource::Spot(l) => l.sample(p, sampler),
+            LightSource::Directional(l) => l.sample(p, sampler),
+            LightSource::Quad(l) => l.sample(p, sampler),
+            LightSource::Disk(l) => l.sample(p, sampler),
+        }
+    }
+
+    fn emitted(&self, ray: &Ray, t_max: f64) -> Option<(f64, Color)> {
+        match self {
+            LightSource::Quad(l) => l.emitted(ray, t_max),
+            LightSource::Disk(l) => l.emitted(ray, t_max),
+            _ => None,
+        }
+    }
+}
+
+fn scale(color: Srgb, factor: f64) -> Color {
+    factor * Color::from(color)
+}
+
+impl Illuminate for Sphere {
+    fn sample(&self, p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let radiance = self.material.emitted()?;
+        let to_center = self.center - p;
+        let dist_squared = to_center.norm_squared();
+        let sin2_theta_max = self.radius * self.radius / dist_squared;
+        if sin2_theta_max >= 1.0 {
+            return None;
+        }
+
+        // Uniformly sample the cone of directions subtended by the sphere
+        let cos_theta_max = (1.0 - sin2_theta_max).sqrt();
+        let (u1, u2) = sampler.get_2d();
+        let cos_theta = 1.0 - u1 * (1.0 - cos_theta_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+
+        let w = unit_vec(to_center);
+        let (u, v) = orthonormal_basis(w);
+        let dir = sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w;
+
+        let dist = dist_squared.sqrt();
+        let distance = dist * cos_theta
+            - (self.radius * self.radius - dist_squared * sin_theta * sin_theta)
+                .max(0.0)
+                .sqrt();
+
+        Some(LightSample {
+            dir,
+            distance,
+            radiance: scale(radiance, 2.0 * PI * (1.0 - cos_theta_max)),
+        })
+    }
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PointLight {
+    pub position: Point3,
+    #[serde_as(as = "SrgbAsArray")]
+    pub color: Srgb,
+    pub power: f64,
+}
+
+impl Illuminate for PointLight {
+    fn sample(&self, p: Point3, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let dist_squared = to_light.norm_squared();
+        let intensity = self.power / (4.0 * PI);
+
+        Some(LightSample {
+            dir: unit_vec(to_light),
+            distance: dist_squared.sqrt(),
+            radiance: scale(self.color, intensity / dist_squared),
+        })
+    }
+}
+
+/// Point light restricted to a cone, fading out between `falloff_start` and
+/// `cone_angle` (half angles in degrees).
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpotLight {
+    pub position: Point3,
+    pub direction: Vec3,
+    #[serde_as(as = "SrgbAsArray")]
+    pub color: Srgb,
+    pub power: f64,
+    pub cone_angle: f64,
+    pub falloff_start: f64,
+}
+
+impl SpotLight {
+    fn falloff(&self, cos_theta: f64, cos_total: f64, cos_start: f64) -> f64 {
+        if cos_theta < cos_total {
+            return 0.0;
+        }
+        if cos_theta >= cos_start {
+            return 1.0;
+        }
+        let delta = (cos_theta - cos_total) / (cos_start - cos_total);
+        delta * delta * delta * delta
+    }
+}
+
+impl Illuminate for SpotLight {
+    fn sample(&self, p: Point3, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let dist_squared = to_light.norm_squared();
+        let dir = unit_vec(to_light);
+
+        let cos_total = deg_to_rad(self.cone_angle).cos();
+        let cos_start = deg_to_rad(self.falloff_start.min(self.cone_angle)).cos();
+        let falloff = self.falloff(dot(-dir, unit_vec(self.direction)), cos_total, cos_start);
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        // Flux is spread over the cone, counting the falloff region as half
+        let intensity = self.power / (2.0 * PI * (1.0 - 0.5 * (cos_start + cos_total)));
+
+        Some(LightSample {
+            dir,
+            distance: dist_squared.sqrt(),
+            radiance: scale(self.color, intensity * falloff / dist_squared),
+        })
+    }
+}
+
+/// Infinitely distant light such as the sun; `direction` is where the light travels.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    #[serde_as(as = "SrgbAsArray")]
+    pub color: Srgb,
+    pub irradiance: f64,
+}
+
+impl Illuminate for DirectionalLight {
+    fn sample(&self, _p: Point3, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        Some(LightSample {
+            dir: -unit_vec(self.direction),
+            distance: f64::INFINITY,
+            radiance: scale(self.color, self.irradiance),
+        })
+    }
+}
+
+/// One sided parallelogram emitting towards `cross(edge_u, edge_v)`.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct QuadLight {
+    pub corner: Point3,
+    pub edge_u: Vec3,
+    pub edge_v: Vec3,
+    #[serde_as(as = "SrgbAsArray")]
+    pub color: Srgb,
+    pub power: f64,
+}
+
+impl QuadLight {
+    fn radiance(&self, area: f64) -> Color {
+        scale(self.color, self.power / (PI * area))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.corner, self.corner + self.edge_u)
+            .union_point(self.corner + self.edge_v)
+            .union_point(self.corner + self.edge_u + self.edge_v)
+    }
+}
+
+impl Illuminate for QuadLight {
+    fn sample(&self, p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let n = cross(self.edge_u, self.edge_v);
+        let area = n.norm();
+        let normal = n / area;
+
+        let (u1, u2) = sampler.get_2d();
+        let point = self.corner + u1 * self.edge_u + u2 * self.edge_v;
+        area_sample(p, point, normal, area, self.radiance(area))
+    }
+
+    fn emitted(&self, ray: &Ray, t_max: f64) -> Option<(f64, Color)> {
+        let n = cross(self.edge_u, self.edge_v);
+        let area = n.norm();
+        let normal = n / area;
+        let t = plane_hit(ray, self.corner, normal, t_max)?;
+
+        // Planar coordinates of the hit point along the two edges
+        let w = n / dot(n, n);
+        let q = ray.at(t) - self.corner;
+        let alpha = dot(w, cross(q, self.edge_v));
+        let beta = dot(w, cross(self.edge_u, q));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some((t, self.radiance(area)))
+    }
+}
+
+/// One sided disk emitting towards `normal`.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DiskLight {
+    pub center: Point3,
+    pub normal: Vec3,
+    pub radius: f64,
+    #[serde_as(as = "SrgbAsArray")]
+    pub color: Srgb,
+    pub power: f64,
+}
+
+impl DiskLight {
+    fn radiance(&self, area: f64) -> Color {
+        scale(self.color, self.power / (PI * area))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        // Extent of a disk along each axis shrinks as its normal aligns with it
+        let n = unit_vec(self.normal);
+        let mut e = Vec3::default();
+        for axis in 0..3 {
+            e[axis] = self.radius * (1.0 - n[axis] * n[axis]).max(0.0).sqrt();
+        }
+        Aabb::new(self.center - e, self.center + e)
+    }
+}
+
+impl Illuminate for DiskLight {
+    fn sample(&self, p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let normal = unit_vec(self.normal);
+        let (u, v) = orthonormal_basis(normal);
+        let area = PI * self.radius * self.radius;
+
+        let (u1, u2) = sampler.get_2d();
+        let (dx, dy) = concentric_sample_disk(u1, u2);
+        let point = self.center + self.radius * (dx * u + dy * v);
+        area_sample(p, point, normal, area, self.radiance(area))
+    }
+
+    fn emitted(&self, ray: &Ray, t_max: f64) -> Option<(f64, Color)> {
+        let normal = unit_vec(self.normal);
+        let t = plane_hit(ray, self.center, normal, t_max)?;
+        if (ray.at(t) - self.center).norm_squared() > self.radius * self.radius {
+            return None;
+        }
+        Some((t, self.radiance(PI * self.radius * self.radius)))
+    }
+}
+
+/// Converts a point sampled uniformly on an emitter's area into a solid angle sample.
+fn area_sample(
+    p: Point3,
+    point: Point3,
+    normal: Vec3,
+    area: f64,
+    radiance: Color,
+) -> Option<LightSample> {
+    let to_light = point - p;
+    let dist_squared = to_light.norm_squared();
+    let dir = unit_vec(to_light);
+    let cos_light = dot(-dir, normal);
+    if cos_light <= 0.0 {
+        return None;
+    }
+
+    Some(LightSample {
+        dir,
+        distance: dist_squared.sqrt(),
+        radiance: area * cos_light / dist_squared * radiance,
+    })
+}
+
+/// Intersection with the front side of a plane.
+fn plane_hit(ray: &Ray, point: Point3, normal: Vec3, t_max: f64) -> Option<f64> {
+    let denom = dot(ray.dir(), normal);
+    if denom >= 0.0 {
+        return None;
+    }
+    let t = dot(point - ray.origin(), normal) / denom;
+    if t <= 0.0001 || t >= t_max {
+        return None;
+    }
+    Some(t)
+}
+
+/// Shirley-Chiu mapping of the unit square onto the unit disk.
+pub fn concentric_sample_disk(u1: f64, u2: f64) -> (f64, f64) {
+    let ox = 2.0 * u1 - 1.0;
+    let oy = 2.0 * u2 - 1.0;
+    if ox == 0.0 && oy == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if ox.abs() > oy.abs() {
+        (ox, PI / 4.0 * (oy / ox))
+    } else {
+        (oy, PI / 2.0 - PI / 4.0 * (ox / oy))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
@@ -0,0 +1,491 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hittable::aabb::Aabb,
+    sampling::AliasTable,
+    utils::deg_to_rad,
+    vec3::{
+        functions::{cross, dot, luminance, unit_vec},
+        Color, Point3, Vec3,
+    },
+};
+
+use super::LightSource;
+
+/// How direct lighting picks the single light it samples at each shading point.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LightSelection {
+    /// Proportionally to the power of the lights, ignoring where they are.
+    Power,
+    /// Through a bounding volume hierarchy favouring lights that are bright,
+    /// close and facing the shading point.
+    #[default]
+    Bvh,
+}
+
+/// Structure built once per scene to choose lights in constant or
+/// logarithmic time, whatever their number.
+#[derive(Debug, Clone)]
+pub enum LightSelector {
+    Power(AliasTable),
+    Bvh(LightBvh),
+}
+
+impl LightSelector {
+    /// `scene` bounds the geometry and is used to estimate the power of
+    /// directional lights. Returns `None` when there is no light to pick.
+    pub fn new(selection: LightSelection, lights: &[LightSource], scene: &Aabb) -> Option<Self> {
+        if lights.is_empty() {
+            return None;
+        }
+
+        match selection {
+            LightSelection::Power => {
+                let (_, scene_radius) = scene.bounding_sphere();
+                let powers: Vec<f64> = lights.iter().map(|l| l.power(scene_radius)).collect();
+                Some(LightSelector::Power(AliasTable::new(&powers)))
+            }
+            LightSelection::Bvh => Some(LightSelector::Bvh(LightBvh::new(lights))),
+        }
+    }
+
+    /// Picks a light for the point `p` with surface normal `n` from `u` in
+    /// [0, 1), returning its index and the probability of having chosen it.
+    pub fn sample(&self, p: Point3, n: Vec3, u: f64) -> Option<(usize, f64)> {
+        match self {
+            LightSelector::Power(table) => {
+                let (index, pmf) = table.sample(u);
+                (pmf > 0.0).then_some((index, pmf))
+            }
+            LightSelector::Bvh(bvh) => bvh.sample(p, n, u),
+        }
+    }
+}
+
+impl LightSource {
+    /// Luminous power of the light, used as its selection weight.
+    fn power(&self, scene_radius: f64) -> f64 {
+        match self {
+            LightSource::Directional(l) => {
+                luminance(Color::from(l.color)) * l.irradiance * PI * scene_radius * scene_radius
+            }
+            _ => self.bounds().map_or(0.0, |b| b.phi),
+        }
+    }
+
+    /// Spatial and directional extent of the emission, `None` for lights
+    /// at infinity.
+    fn bounds(&self) -> Option<LightBounds> {
+        match self {
+            LightSource::Sphere(s) => {
+                // A sphere without an emissive material gives no light
+                let radiance = s
+                    .material
+                    .emitted()
+                    .map_or(0.0, |emitted| luminance(Color::from(emitted)));
+                Some(LightBounds {
+                    bounds: s.bounding_box(),
+                    phi: radiance * PI * 4.0 * PI * s.radius * s.radius,
+                    cone: DirectionCone::entire_sphere(),
+                    cos_theta_e: 0.0,
+                })
+            }
+            LightSource::Point(l) => Some(LightBounds {
+                bounds: Aabb::point(l.position),
+                phi: luminance(Color::from(l.color)) * l.power,
+                cone: DirectionCone::entire_sphere(),
+                cos_theta_e: 0.0,
+            }),
+            LightSource::Spot(l) => {
+                let theta_total = deg_to_rad(l.cone_angle);
+                let theta_start = deg_to_rad(l.falloff_start.min(l.cone_angle));
+                let (cos_total, cos_start) = (theta_total.cos(), theta_start.cos());
+                let intensity = l.power / (2.0 * PI * (1.0 - 0.5 * (cos_start + cos_total)));
+                Some(LightBounds {
+                    bounds: Aabb::point(l.position),
+                    phi: luminance(Color::from(l.color)) * intensity * 4.0 * PI,
+                    cone: DirectionCone::new(unit_vec(l.direction), cos_start),
+                    cos_theta_e: (theta_total - theta_start).cos(),
+                })
+            }
+            LightSource::Directional(_) => None,
+            LightSource::Quad(l) => Some(LightBounds {
+                bounds: l.bounding_box(),
+                phi: luminance(Color::from(l.color)) * l.power,
+                cone: DirectionCone::new(unit_vec(cross(l.edge_u, l.edge_v)), 1.0),
+                cos_theta_e: 0.0,
+            }),
+            LightSource::Disk(l) => Some(LightBounds {
+                bounds: l.bounding_box(),
+                phi: luminance(Color::from(l.color)) * l.power,
+                cone: DirectionCone::new(unit_vec(l.normal), 1.0),
+                cos_theta_e: 0.0,
+            }),
+        }
+    }
+}
+
+/// Set of directions within an angle of a central axis.
+#[derive(Debug, Clone, Copy)]
+struct DirectionCone {
+    w: Vec3,
+    cos_theta: f64,
+}
+
+impl DirectionCone {
+    fn new(w: Vec3, cos_theta: f64) -> Self {
+        Self { w, cos_theta }
+    }
+
+    fn entire_sphere() -> Self {
+        Self::new(Vec3::new(0.0, 0.0, 1.0), -1.0)
+    }
+
+    /// Smallest cone holding both cones.
+    fn union(&self, other: &DirectionCone) -> Self {
+        let theta_a = safe_acos(self.cos_theta);
+        let theta_b = safe_acos(other.cos_theta);
+        let theta_d = safe_acos(dot(self.w, other.w));
+        if (theta_d + theta_b).min(PI) <= theta_a {
+            return *self;
+        }
+        if (theta_d + theta_a).min(PI) <= theta_b {
+            return *other;
+        }
+
+        let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+        if theta_o >= PI {
+            return Self::entire_sphere();
+        }
+
+        // Turn the first axis towards the second until it bisects the union
+        let theta_r = theta_o - theta_a;
+        let axis = cross(self.w, other.w);
+        if axis.norm_squared() == 0.0 {
+            return Self::entire_sphere();
+        }
+        Self::new(rotate(self.w, unit_vec(axis), theta_r), theta_o.cos())
+    }
+}
+
+/// Conservative bounds on the emission of one light or a group of lights:
+/// where they are, how much they emit and in which directions.
+#[derive(Debug, Clone, Copy)]
+struct LightBounds {
+    bounds: Aabb,
+    phi: f64,
+    /// Directions of the surface normals, or of the spot axes
+    cone: DirectionCone,
+    /// Cosine of the angle beyond the normals over which light is still emitted
+    cos_theta_e: f64,
+}
+
+impl LightBounds {
+    fn union(&self, other: &LightBounds) -> Self {
+        if self.phi == 0.0 {
+            return *other;
+        }
+        if other.phi == 0.0 {
+            return *self;
+        }
+        Self {
+            bounds: self.bounds.union(&other.bounds),
+            phi: self.phi + other.phi,
+            cone: self.cone.union(&other.cone),
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+        }
+    }
+
+    /// Upper bound of the contribution the lights can make at `p`, whose
+    /// surface has normal `n` (Conty Estevez and Kulla).
+    fn importance(&self, p: Point3, n: Vec3) -> f64 {
+        let pc = self.bounds.centroid();
+        let d2 = (p - pc)
+            .norm_squared()
+            .max(self.bounds.diagonal().norm() / 2.0);
+
+        let to_p = p - pc;
+        let wi = if to_p.norm_squared() > 0.0 {
+            unit_vec(to_p)
+        } else {
+            self.cone.w
+        };
+
+        // Angle between the emission axis and the point, reduced by the
+        // spread of the cone and by the angle the bounds subtend
+        let cos_theta_w = dot(self.cone.w, wi);
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+        let cos_theta_o = self.cone.cos_theta;
+        let sin_theta_o = safe_sqrt(1.0 - cos_theta_o * cos_theta_o);
+        let cos_theta_b = self.cos_subtended(p);
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.phi * cos_theta_p / d2;
+
+        // Incidence on the receiving surface
+        if n.norm_squared() > 0.0 {
+            let cos_theta_i = dot(wi, n).abs();
+            let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+
+        importance.max(0.0)
+    }
+
+    /// Cosine of the half angle of the cone of directions from `p` that
+    /// contains the bounds.
+    fn cos_subtended(&self, p: Point3) -> f64 {
+        let (center, radius) = self.bounds.bounding_sphere();
+        let d2 = (p - center).norm_squared();
+        if d2 < radius * radius {
+            return -1.0;
+        }
+        safe_sqrt(1.0 - radius * radius / d2)
+    }
+
+    /// Surface area orientation heuristic cost of a node splitting along `axis`.
+    fn cost(&self, axis: usize, extent: &Aabb) -> f64 {
+        let theta_o = safe_acos(self.cone.cos_theta);
+        let theta_e = safe_acos(self.cos_theta_e);
+        let theta_w = (theta_o + theta_e).min(PI);
+        let sin_theta_o = safe_sqrt(1.0 - self.cone.cos_theta * self.cone.cos_theta);
+        let m_omega = 2.0 * PI * (1.0 - self.cone.cos_theta)
+            + PI / 2.0
+                * (2.0 * theta_w * sin_theta_o
+                    - (theta_o - 2.0 * theta_w).cos()
+                    - 2.0 * theta_o * sin_theta_o
+                    + self.cone.cos_theta);
+
+        // Penalize thin slabs, whose children end up overlapping a lot
+        let d = extent.diagonal();
+        let max_length = d.x().max(d.y()).max(d.z());
+        let k_r = if d[axis] > 0.0 {
+            max_length / d[axis]
+        } else {
+            1.0
+        };
+
+        self.phi * m_omega * k_r * self.bounds.surface_area()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LightNodeKind {
+    Leaf(usize),
+    /// The first child directly follows its parent in the node array
+    Interior {
+        second_child: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LightNode {
+    bounds: LightBounds,
+    kind: LightNodeKind,
+}
+
+/// Bounding volume hierarchy over the lights with a position, traversed
+/// stochastically according to the importance of each child.
+#[derive(Debug, Clone)]
+pub struct LightBvh {
+    nodes: Vec<LightNode>,
+    /// Directional lights, picked uniformly
+    infinite: Vec<usize>,
+}
+
+const SPLIT_BUCKETS: usize = 12;
+
+impl LightBvh {
+    pub fn new(lights: &[LightSource]) -> Self {
+        let mut infinite = Vec::new();
+        let mut entries = Vec::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.phi > 0.0 => entries.push((i, bounds)),
+                Some(_) => {}
+                None => infinite.push(i),
+            }
+        }
+
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * entries.len()),
+            infinite,
+        };
+        if !entries.is_empty() {
+            bvh.build(&mut entries);
+        }
+        bvh
+    }
+
+    /// Appends the subtree over `entries` and returns the index of its root.
+    fn build(&mut self, entries: &mut [(usize, LightBounds)]) -> usize {
+        if let [(light, bounds)] = entries {
+            self.nodes.push(LightNode {
+                bounds: *bounds,
+                kind: LightNodeKind::Leaf(*light),
+            });
+            return self.nodes.len() - 1;
+        }
+
+        let total = entries
+            .iter()
+            .skip(1)
+            .fold(entries[0].1, |acc, (_, b)| acc.union(b));
+        let centroids = entries.iter().fold(Aabb::default(), |acc, (_, b)| {
+            acc.union_point(b.bounds.centroid())
+        });
+
+        let bucket_of = |bounds: &LightBounds, axis: usize| {
+            let offset = centroids.offset(bounds.bounds.centroid())[axis];
+            ((offset * SPLIT_BUCKETS as f64) as usize).min(SPLIT_BUCKETS - 1)
+        };
+
+        // Find the cheapest split between buckets of centroids along any axis
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            if centroids.max[axis] == centroids.min[axis] {
+                continue;
+            }
+
+            let mut buckets: [Option<LightBounds>; SPLIT_BUCKETS] = [None; SPLIT_BUCKETS];
+            for (_, bounds) in entries.iter() {
+                let b = bucket_of(bounds, axis);
+                buckets[b] = Some(buckets[b].map_or(*bounds, |acc| acc.union(bounds)));
+            }
+
+            for split in 0..SPLIT_BUCKETS - 1 {
+                let side = |range: &[Option<LightBounds>]| {
+                    range
+                        .iter()
+                        .flatten()
+                        .fold(None, |acc: Option<LightBounds>, b| {
+                            Some(acc.map_or(*b, |acc| acc.union(b)))
+                        })
+                };
+                let cost = side(&buckets[..=split]).map_or(0.0, |b| b.cost(axis, &total.bounds))
+                    + side(&buckets[split + 1..]).map_or(0.0, |b| b.cost(axis, &total.bounds));
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let mid = match best {
+            Some((_, axis, split)) => {
+                let mut mid = 0;
+                for i in 0..entries.len() {
+                    if bucket_of(&entries[i].1, axis) <= split {
+                        entries.swap(i, mid);
+                        mid += 1;
+                    }
+                }
+                mid
+            }
+            None => 0,
+        };
+        // All the centroids in one place or on one side: split the list in half
+        let mid = if mid == 0 || mid == entries.len() {
+            entries.len() / 2
+        } else {
+            mid
+        };
+
+        let node = self.nodes.len();
+        self.nodes.push(LightNode {
+            bounds: total,
+            kind: LightNodeKind::Interior { second_child: 0 },
+        });
+        let (first, second) = entries.split_at_mut(mid);
+        self.build(first);
+        let second_child = self.build(second);
+        self.nodes[node].kind = LightNodeKind::Interior { second_child };
+
+        node
+    }
+
+    fn sample(&self, p: Point3, n: Vec3, u: f64) -> Option<(usize, f64)> {
+        let n_infinite = self.infinite.len();
+        let has_bvh = !self.nodes.is_empty();
+        let p_infinite = n_infinite as f64 / (n_infinite as f64 + if has_bvh { 1.0 } else { 0.0 });
+
+        if u < p_infinite {
+            let index = ((u / p_infinite * n_infinite as f64) as usize).min(n_infinite - 1);
+            return Some((self.infinite[index], p_infinite / n_infinite as f64));
+        }
+        if !has_bvh {
+            return None;
+        }
+
+        // Reuse the remaining part of the random number at every level
+        let mut u = (u - p_infinite) / (1.0 - p_infinite);
+        let mut pmf = 1.0 - p_infinite;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            match node.kind {
+                LightNodeKind::Leaf(light) => {
+                    if index > 0 || node.bounds.importance(p, n) > 0.0 {
+                        return Some((light, pmf));
+                    }
+                    return None;
+                }
+                LightNodeKind::Interior { second_child } => {
+                    let c0 = self.nodes[index + 1].bounds.importance(p, n);
+                    let c1 = self.nodes[second_child].bounds.importance(p, n);
+                    if c0 == 0.0 && c1 == 0.0 {
+                        return None;
+                    }
+
+                    let p0 = c0 / (c0 + c1);
+                    if u < p0 {
+                        index += 1;
+                        u = (u / p0).min(1.0 - f64::EPSILON);
+                        pmf *= p0;
+                    } else {
+                        index = second_child;
+                        u = ((u - p0) / (1.0 - p0)).min(1.0 - f64::EPSILON);
+                        pmf *= 1.0 - p0;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_acos(x: f64) -> f64 {
+    x.clamp(-1.0, 1.0).acos()
+}
+
+/// Cosine of the difference of two angles, clamped so it never goes below zero.
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        return 1.0;
+    }
+    cos_a * cos_b + sin_a * sin_b
+}
+
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        return 0.0;
+    }
+    sin_a * cos_b - cos_a * sin_b
+}
+
+/// Rotates `v` by `angle` radians around the unit vector `axis` (Rodrigues).
+fn rotate(v: Vec3, axis: Vec3, angle: f64) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    cos * v + sin * cross(axis, v) + (1.0 - cos) * dot(axis, v) * axis
+}
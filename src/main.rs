@@ -1,14 +1,36 @@
 #![allow(dead_code)]
 
-use crate::state::State;
-use renderer::render;
+use crate::{
+    cli::{Args, USAGE},
+    state::State,
+};
 
+mod adaptive;
+mod animation;
+mod aov;
+mod background;
 mod camera;
+mod cli;
+mod crop;
+mod denoise;
+mod distributed;
+mod exposure;
+mod film;
+mod filter;
 mod hittable;
+mod light;
 mod material;
+mod mlt;
+mod output;
+mod progressive;
 mod ray;
 mod renderer;
+mod sampler;
+mod sampling;
+mod sky;
 mod state;
+mod tile;
+mod tonemap;
 mod utils;
 mod vec3;
 
@@ -16,9 +38,35 @@ mod vec3;
 mod tests;
 
 fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{USAGE}");
+        return;
+    }
+
+    if let Some(address) = &args.worker {
+        // Workers get their state from the coordinator
+        if let Err(e) = distributed::serve(address) {
+            eprintln!("error listening on {address}: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // World and Camera initialization
-    let state = State::from_json("state.json");
+    let mut state = State::from_json(args.state_file());
+    if let Err(e) = args.apply(&mut state) {
+        eprintln!("{e}");
+        std::process::exit(2);
+    }
 
     // Render
-    render(state);
+    let frames = args.frame_range(&state);
+    animation::render_frames(&mut state, frames);
 }
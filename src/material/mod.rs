@@ -4,15 +4,13 @@ use serde::{Deserialize, Serialize};
 use crate::{
     hittable::HitRecord,
     ray::Ray,
-    utils::random_float,
-    vec3::{
-        functions::{dot, reflect, refract, unit_vec},
-        Vec3,
-    },
+    sampler::Sampler,
+    sampling::{uniform_ball, uniform_sphere},
+    vec3::functions::{dot, reflect, refract, unit_vec},
 };
 
 serde_with::serde_conv!(
-    SrgbAsArray,
+    pub SrgbAsArray,
     Srgb,
     |srgb: &Srgb| [srgb.red, srgb.green, srgb.blue],
     |value: [f32; 3]| -> Result<_, std::convert::Infallible> {
@@ -20,7 +18,7 @@ serde_with::serde_conv!(
     }
 );
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Material {
     Lambertian(Lambertian),
     Metal(Metal),
@@ -29,7 +27,12 @@ pub enum Material {
 }
 
 pub trait Scatterable {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Option<Ray>, Srgb)>;
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Option<Ray>, Srgb)>;
 }
 
 impl Default for Material {
@@ -52,21 +55,42 @@ impl Material {
     pub fn new_dielectric(ir: f64) -> Self {
         Self::Dielectric(Dielectric { ir })
     }
+
+    /// Radiance emitted by the surface, if it is a light.
+    pub fn emitted(&self) -> Option<Srgb> {
+        match self {
+            Material::Light(_) => Some(Srgb::new(1.0, 1.0, 1.0)),
+            _ => None,
+        }
+    }
+
+    /// Albedo of perfectly diffuse materials, the only ones lit by direct light sampling.
+    pub fn diffuse_albedo(&self) -> Option<Srgb> {
+        match self {
+            Material::Lambertian(l) => Some(l.albedo),
+            _ => None,
+        }
+    }
 }
 
 impl Scatterable for Material {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Option<Ray>, Srgb)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Option<Ray>, Srgb)> {
         match self {
-            Material::Lambertian(l) => l.scatter(ray, hit_record),
-            Material::Metal(m) => m.scatter(ray, hit_record),
-            Material::Dielectric(d) => d.scatter(ray, hit_record),
-            Material::Light(l) => l.scatter(ray, hit_record),
+            Material::Lambertian(l) => l.scatter(ray, hit_record, sampler),
+            Material::Metal(m) => m.scatter(ray, hit_record, sampler),
+            Material::Dielectric(d) => d.scatter(ray, hit_record, sampler),
+            Material::Light(l) => l.scatter(ray, hit_record, sampler),
         }
     }
 }
 
 #[serde_with::serde_as]
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Light {}
 
 impl Light {
@@ -77,32 +101,46 @@ impl Light {
 
 #[allow(unused)]
 impl Scatterable for Light {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Option<Ray>, Srgb)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Option<Ray>, Srgb)> {
        Some((None, Srgb::new(1.0, 1.0, 1.0))) 
     }
 }
 
 #[serde_with::serde_as]
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Lambertian {
     #[serde_as(as = "SrgbAsArray")]
     pub albedo: Srgb,
 }
 
 impl Scatterable for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit_record: &HitRecord) -> Option<(Option<Ray>, Srgb)> {
-        let mut scatter_direction = hit_record.normal + Vec3::random_in_unit_sphere();
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Option<Ray>, Srgb)> {
+        // The normal plus a point on the unit sphere is distributed by the
+        // cosine, so weighting by the albedo alone matches the albedo / PI
+        // BRDF that direct lighting uses. Points inside the ball would weight
+        // the directions near the normal too heavily.
+        let mut scatter_direction = hit_record.normal + uniform_sphere(sampler.get_2d());
         if scatter_direction.near_zero() {
             scatter_direction = hit_record.normal
         }
-        let scattered = Ray::new(hit_record.p, scatter_direction);
+        let scattered = Ray::with_time(hit_record.p, scatter_direction, ray.time());
         let attenuation = self.albedo;
         Some((Some(scattered), attenuation))
     }
 }
 
 #[serde_with::serde_as]
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Metal {
     #[serde_as(as = "SrgbAsArray")]
     pub albedo: Srgb,
@@ -110,11 +148,17 @@ pub struct Metal {
 }
 
 impl Scatterable for Metal {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Option<Ray>, Srgb)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Option<Ray>, Srgb)> {
         let reflected = reflect(unit_vec(ray.dir()), hit_record.normal);
-        let scattered = Ray::new(
+        let scattered = Ray::with_time(
             hit_record.p,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
+            reflected + self.fuzz * uniform_ball(sampler.get_2d(), sampler.get_1d()),
+            ray.time(),
         );
         let attenuation = self.albedo;
         if dot(scattered.dir(), hit_record.normal) > 0. {
@@ -125,13 +169,18 @@ impl Scatterable for Metal {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Dielectric {
     pub ir: f64,
 }
 
 impl Scatterable for Dielectric {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Option<Ray>, Srgb)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Option<Ray>, Srgb)> {
         let attenuation = Srgb::new(1., 1., 1.);
         let refraction_ratio = if hit_record.front_face {
             1.0 / self.ir
@@ -145,13 +194,13 @@ impl Scatterable for Dielectric {
 
         let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
 
-        if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_float() {
+        if cannot_refract || reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
             let reflected = reflect(unit_direction, hit_record.normal);
-            let scattered = Ray::new(hit_record.p, reflected);
+            let scattered = Ray::with_time(hit_record.p, reflected, ray.time());
             Some((Some(scattered), attenuation))
         } else {
             let direction = refract(unit_direction, hit_record.normal, refraction_ratio);
-            let scattered = Ray::new(hit_record.p, direction);
+            let scattered = Ray::with_time(hit_record.p, direction, ray.time());
             Some((Some(scattered), attenuation))
         }
     }
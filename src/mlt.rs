@@ -0,0 +1,338 @@
+use std::time::Instant;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use palette::Srgb;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator,
+    IntoParallelRefMutIterator, ParallelIterator,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    renderer::ray_color,
+    sampler::{hash, Sampler},
+    state::State,
+    vec3::{functions::luminance, Color},
+};
+
+/// Bytes the films of the chains running at once may take, which limits how
+/// many run in parallel on large images.
+const CHAIN_FILMS_BUDGET: usize = 1 << 30;
+
+/// Primary-sample-space Metropolis light transport (Kelemen et al.) settings.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetropolisConfig {
+    pub mutations_per_pixel: u32,
+    pub bootstrap_samples: u32,
+    pub chains: u32,
+    pub large_step_probability: f64,
+    pub sigma: f64,
+}
+
+impl Default for MetropolisConfig {
+    fn default() -> Self {
+        Self {
+            mutations_per_pixel: 100,
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            large_step_probability: 0.3,
+            sigma: 0.01,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    last_modification: u64,
+    value_backup: f64,
+    modify_backup: u64,
+}
+
+impl PrimarySample {
+    fn backup(&mut self) {
+        self.value_backup = self.value;
+        self.modify_backup = self.last_modification;
+    }
+
+    fn restore(&mut self) {
+        self.value = self.value_backup;
+        self.last_modification = self.modify_backup;
+    }
+}
+
+/// Lazily mutated vector of primary samples driving a single Markov chain.
+struct MltSampler {
+    rng: StdRng,
+    sigma: f64,
+    large_step_probability: f64,
+    x: Vec<PrimarySample>,
+    current_iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    sample_index: usize,
+}
+
+impl MltSampler {
+    fn new(seed: u64, config: &MetropolisConfig) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            sigma: config.sigma,
+            large_step_probability: config.large_step_probability,
+            x: Vec::new(),
+            current_iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            sample_index: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.current_iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.current_iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        let current_iteration = self.current_iteration;
+        for xi in self
+            .x
+            .iter_mut()
+            .filter(|xi| xi.last_modification == current_iteration)
+        {
+            xi.restore();
+        }
+        self.current_iteration -= 1;
+    }
+
+    fn next(&mut self) -> f64 {
+        let index = self.sample_index;
+        self.sample_index += 1;
+        if index >= self.x.len() {
+            // A dimension the chain never used is a fresh uniform sample
+            let value = self.rng.gen();
+            self.x.push(PrimarySample {
+                value,
+                last_modification: self.current_iteration,
+                value_backup: value,
+                modify_backup: self.current_iteration,
+            });
+            return value;
+        }
+        self.ensure_ready(index);
+        self.x[index].value
+    }
+
+    fn ensure_ready(&mut self, index: usize) {
+        let large_step = self.large_step;
+        let last_large_step = self.last_large_step;
+        let current_iteration = self.current_iteration;
+
+        // Samples untouched since the last accepted large step are reset first
+        if self.x[index].last_modification < last_large_step {
+            self.x[index].value = self.rng.gen();
+            self.x[index].last_modification = last_large_step;
+        }
+
+        self.x[index].backup();
+        if large_step {
+            self.x[index].value = self.rng.gen();
+        } else {
+            // Apply all the small steps skipped since the sample was last used at once
+            let n_small = (current_iteration - self.x[index].last_modification) as f64;
+            let sigma = self.sigma * n_small.sqrt();
+            let value = self.x[index].value + sigma * self.gaussian();
+            self.x[index].value = value - value.floor();
+        }
+        self.x[index].last_modification = current_iteration;
+    }
+
+    fn gaussian(&mut self) -> f64 {
+        let u1: f64 = 1.0 - self.rng.gen::<f64>();
+        let u2: f64 = self.rng.gen();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// Every dimension of a path is a primary sample of the chain, so the film
+/// position takes the first two.
+impl Sampler for MltSampler {
+    fn start_pixel_sample(&mut self, _pixel: (u32, u32), _sample_index: u32) {
+        self.sample_index = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.next(), self.next())
+    }
+}
+
+/// Traces one path whose every random decision is read from `sampler`,
+/// returning the film pixel and radiance it produced.
+fn trace(sampler: &mut MltSampler, state: &State) -> (usize, Srgb) {
+    let width = state.width.unwrap() as usize;
+    let height = state.height as usize;
+
+    sampler.start_pixel_sample((0, 0), 0);
+    let (sx, sy) = sampler.get_2d();
+    let film_x = sx * width as f64;
+    let film_y = sy * height as f64;
+    // The film spans [0, 1] on both axes, which holds for frames a single
+    // pixel wide or tall
+    let u = film_x / width as f64;
+    let v = (height as f64 - film_y) / height as f64;
+    let color = match state.camera.get_ray(u, v, sampler) {
+        Some(r) => ray_color(r, state, sampler),
+        None => Srgb::new(0.0, 0.0, 0.0),
+    };
+
+    let x = (film_x as usize).min(width - 1);
+    let y = (film_y as usize).min(height - 1);
+    (y * width + x, color)
+}
+
+fn splat(film: &mut [f64], index: usize, color: Srgb, weight: f64) {
+    film[index * 3] += weight * color.red as f64;
+    film[index * 3 + 1] += weight * color.green as f64;
+    film[index * 3 + 2] += weight * color.blue as f64;
+}
+
+/// Runs chain number `chain` for `mutations` steps, splatting its states
+/// into `film`.
+fn run_chain(
+    state: &State,
+    config: &MetropolisConfig,
+    cdf: &[f64],
+    chain: u64,
+    mutations: u64,
+    film: &mut [f64],
+) {
+    // Pick the initial state proportionally to its contribution
+    let mut rng = StdRng::seed_from_u64(hash(&[state.seed, chain, 1]));
+    let target: f64 = rng.gen();
+    let index = cdf.partition_point(|&c| c < target).min(cdf.len() - 1) as u64;
+
+    let mut sampler = MltSampler::new(hash(&[state.seed, index]), config);
+    let (mut current_index, mut current_l) = trace(&mut sampler, state);
+    sampler.accept();
+
+    for _ in 0..mutations {
+        sampler.start_iteration();
+        let (proposed_index, proposed_l) = trace(&mut sampler, state);
+
+        let current_c = luminance(Color::from(current_l));
+        let proposed_c = luminance(Color::from(proposed_l));
+        let accept = if current_c > 0.0 {
+            f64::min(1.0, proposed_c / current_c)
+        } else {
+            1.0
+        };
+
+        // Splat both states weighted by their expected contribution
+        if accept > 0.0 && proposed_c > 0.0 {
+            splat(film, proposed_index, proposed_l, accept / proposed_c);
+        }
+        if current_c > 0.0 {
+            splat(film, current_index, current_l, (1.0 - accept) / current_c);
+        }
+
+        if rng.gen::<f64>() < accept {
+            current_index = proposed_index;
+            current_l = proposed_l;
+            sampler.accept();
+        } else {
+            sampler.reject();
+        }
+    }
+}
+
+/// Estimates the mean luminance `b` of the image from independent paths,
+/// returning it with the cumulative distribution of their contributions the
+/// chains start from.
+pub fn bootstrap(state: &State, config: &MetropolisConfig) -> (f64, Vec<f64>) {
+    let bootstrap_weights: Vec<f64> = (0..config.bootstrap_samples as u64)
+        .into_par_iter()
+        .map(|index| {
+            let mut sampler = MltSampler::new(hash(&[state.seed, index]), config);
+            let (_index, color) = trace(&mut sampler, state);
+            luminance(Color::from(color))
+        })
+        .collect();
+    let weight_sum: f64 = bootstrap_weights.iter().sum();
+    let b = weight_sum / config.bootstrap_samples.max(1) as f64;
+
+    let mut cdf = Vec::with_capacity(bootstrap_weights.len());
+    let mut acc = 0.0;
+    for w in bootstrap_weights.iter() {
+        acc += w;
+        cdf.push(acc / weight_sum);
+    }
+    (b, cdf)
+}
+
+/// Renders `state` with PSSMLT and returns the linear radiance of every pixel,
+/// normalized so that its mean matches the path traced image.
+pub fn render(state: &State, config: &MetropolisConfig) -> Vec<f64> {
+    let image_width = state.width.unwrap() as usize;
+    let image_height = state.height as usize;
+    let n_pixels = image_width * image_height;
+
+    let start = Instant::now();
+
+    let (b, cdf) = bootstrap(state, config);
+    if b <= 0.0 || config.mutations_per_pixel == 0 {
+        return vec![0.0; n_pixels * 3];
+    }
+
+    let total_mutations = config.mutations_per_pixel as u64 * n_pixels as u64;
+    let chains = config.chains.max(1) as u64;
+
+    let pb = ProgressBar::new(chains);
+    let sty = ProgressStyle::with_template("[{elapsed_precise}] {prefix} {bar:40.cyan/blue} {msg}")
+        .unwrap();
+    pb.set_style(sty);
+    pb.set_prefix("[Metropolis]");
+
+    // Chains run in parallel, each on its own film, and the films are added
+    // in chain order so the image does not depend on how the threads were
+    // scheduled. There are as many films as threads, within the budget.
+    let mut film = vec![0.0; n_pixels * 3];
+    let film_bytes = n_pixels * 3 * std::mem::size_of::<f64>();
+    let batch_size = rayon::current_num_threads()
+        .min(CHAIN_FILMS_BUDGET / film_bytes.max(1))
+        .max(1);
+    let mut chain_films = vec![vec![0.0; n_pixels * 3]; batch_size.min(chains as usize)];
+    let chain_ids: Vec<u64> = (0..chains).collect();
+    for batch in chain_ids.chunks(batch_size) {
+        chain_films
+            .par_iter_mut()
+            .zip(batch.par_iter())
+            .for_each(|(chain_film, &chain)| {
+                let mutations =
+                    total_mutations / chains + u64::from(chain < total_mutations % chains);
+                chain_film.fill(0.0);
+                run_chain(state, config, &cdf, chain, mutations, chain_film);
+                pb.inc(1);
+            });
+        for chain_film in &chain_films[..batch.len()] {
+            for (pixel, value) in film.iter_mut().zip(chain_film) {
+                *pixel += value;
+            }
+        }
+    }
+    pb.finish_with_message("[Render Complete!]");
+    println!("Time elapsed: {}ms", start.elapsed().as_millis());
+
+    let scale = b / config.mutations_per_pixel as f64;
+    film.into_iter().map(|v| v * scale).collect()
+}
@@ -0,0 +1,553 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use image::{
+    hdr::{HDRDecoder, HDREncoder},
+    jpeg::JPEGEncoder,
+    png::PNGEncoder,
+    ppm::PPMEncoder,
+    ColorType, Rgb,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    tile::Tile,
+    tonemap::{srgb_oetf, ToneMapping},
+    vec3::Color,
+};
+
+/// Image file the render is written to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Output {
+    pub path: String,
+    /// Encoding of the file, guessed from the extension of `path` when missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            path: "data/render.png".to_string(),
+            format: None,
+        }
+    }
+}
+
+/// Encoding of an output image. The 8 and 16 bit formats are tone mapped,
+/// the floating point ones hold the linear radiance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    Png,
+    /// PNG with 16 bits per channel, avoiding banding in smooth gradients
+    Png16,
+    Jpeg {
+        /// From 1, the smallest file, to 100, the best looking image
+        #[serde(default = "default_quality")]
+        quality: u8,
+    },
+    Bmp,
+    Tga,
+    Ppm,
+    Exr,
+    Hdr,
+    Pfm,
+}
+
+fn default_quality() -> u8 {
+    90
+}
+
+impl OutputFormat {
+    /// Format named `name`, as given on the command line or as the extension
+    /// of a file.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let format = match name.to_lowercase().as_str() {
+            "png" => OutputFormat::Png,
+            "png16" => OutputFormat::Png16,
+            "jpg" | "jpeg" => OutputFormat::Jpeg {
+                quality: default_quality(),
+            },
+            "bmp" => OutputFormat::Bmp,
+            "tga" => OutputFormat::Tga,
+            "ppm" => OutputFormat::Ppm,
+            "exr" => OutputFormat::Exr,
+            "hdr" => OutputFormat::Hdr,
+            "pfm" => OutputFormat::Pfm,
+            _ => return None,
+        };
+        Some(format)
+    }
+}
+
+impl Output {
+    pub fn format(&self) -> Result<OutputFormat, String> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        Path::new(&self.path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(OutputFormat::from_name)
+            .ok_or_else(|| format!("cannot guess the image format of {}", self.path))
+    }
+
+    /// Writes the linear image `pixels`, given row by row from the top,
+    /// creating the missing directories of the path.
+    pub fn write(
+        &self,
+        pixels: &[Color],
+        bounds: (usize, usize),
+        tone_mapping: &ToneMapping,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let format = self.format()?;
+        create_parent_dir(&self.path)?;
+        match format {
+            OutputFormat::Exr | OutputFormat::Hdr | OutputFormat::Pfm => {
+                write_float_image(&self.path, format, pixels, bounds)
+            }
+            _ => self.write_encoded(format, &encode(format, pixels, tone_mapping), bounds),
+        }
+    }
+
+    /// Pastes the linear `pixels` of `crop` into the image already written
+    /// at the output path, which must have the `bounds` of the whole frame
+    /// and the format of the output.
+    pub fn composite(
+        &self,
+        pixels: &[Color],
+        crop: &Tile,
+        bounds: (usize, usize),
+        tone_mapping: &ToneMapping,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let format = self.format()?;
+        match format {
+            OutputFormat::Exr | OutputFormat::Hdr | OutputFormat::Pfm => {
+                composite_float_image(&self.path, format, pixels, crop, bounds)
+            }
+            _ => {
+                check_limits(&self.path, format, bounds)?;
+                let (width, height, mut data) = read_encoded(&self.path, format)?;
+                check_size(&self.path, (width, height), bounds)?;
+                let encoded = encode(format, pixels, tone_mapping);
+                crop.paste(&mut data, &encoded, bounds.0, encoded.len() / crop.len());
+                self.write_encoded(format, &data, bounds)
+            }
+        }
+    }
+
+    /// Writes bytes made by `encode` for a file of the given 8 or 16 bit `format`.
+    fn write_encoded(
+        &self,
+        format: OutputFormat,
+        data: &[u8],
+        bounds: (usize, usize),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        check_limits(&self.path, format, bounds)?;
+        match format {
+            OutputFormat::Png => write_png(&self.path, data, bounds)?,
+            OutputFormat::Png16 => encode_png(&self.path, data, bounds, ColorType::RGB(16))?,
+            OutputFormat::Jpeg { quality } => {
+                let mut output = BufWriter::new(File::create(&self.path)?);
+                JPEGEncoder::new_with_quality(&mut output, quality).encode(
+                    data,
+                    bounds.0 as u32,
+                    bounds.1 as u32,
+                    ColorType::RGB(8),
+                )?;
+                output.flush()?;
+            }
+            OutputFormat::Bmp => write_bmp(&self.path, data, bounds)?,
+            OutputFormat::Tga => write_tga(&self.path, data, bounds)?,
+            OutputFormat::Ppm => {
+                let mut output = BufWriter::new(File::create(&self.path)?);
+                PPMEncoder::new(&mut output).encode(
+                    data,
+                    bounds.0 as u32,
+                    bounds.1 as u32,
+                    ColorType::RGB(8),
+                )?;
+                output.flush()?;
+            }
+            OutputFormat::Exr | OutputFormat::Hdr | OutputFormat::Pfm => {
+                unreachable!("floating point formats are not encoded")
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tone mapped and sRGB encoded bytes of `pixels` for an 8 or 16 bit format.
+fn encode(format: OutputFormat, pixels: &[Color], tone_mapping: &ToneMapping) -> Vec<u8> {
+    match format {
+        OutputFormat::Png16 => pixels
+            .iter()
+            .flat_map(|&c| {
+                let c = tone_mapping.apply(c);
+                [c.x(), c.y(), c.z()]
+            })
+            // PNG stores 16 bit samples big endian
+            .flat_map(|v| ((srgb_oetf(v) * 65535.0).round() as u16).to_be_bytes())
+            .collect(),
+        _ => pixels
+            .iter()
+            .flat_map(|&c| tone_mapping.encode(c))
+            .collect(),
+    }
+}
+
+/// Size and bytes, as made by `encode`, of an 8 bit image file.
+fn read_encoded(
+    path: &str,
+    format: OutputFormat,
+) -> Result<(usize, usize, Vec<u8>), Box<dyn std::error::Error>> {
+    match format {
+        // The decoder only gives 8 bit samples, which would lose the precision
+        // of the rest of the image
+        OutputFormat::Png16 => Err("cannot composite into 16 bit PNGs".into()),
+        _ => {
+            let image = image::open(path)?.to_rgb();
+            let (width, height) = image.dimensions();
+            Ok((width as usize, height as usize, image.into_raw()))
+        }
+    }
+}
+
+fn check_size(
+    path: &str,
+    size: (usize, usize),
+    bounds: (usize, usize),
+) -> Result<(), Box<dyn std::error::Error>> {
+    if size != bounds {
+        return Err(format!(
+            "{path} is {}x{} pixels instead of the {}x{} of the frame",
+            size.0, size.1, bounds.0, bounds.1
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Checks that an image of `bounds` fits in a file of the 8 or 16 bit
+/// `format`, TGA and JPEG storing sizes on 16 bits.
+fn check_limits(
+    path: &str,
+    format: OutputFormat,
+    bounds: (usize, usize),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let max = match format {
+        OutputFormat::Tga | OutputFormat::Jpeg { .. } => u16::MAX as usize,
+        _ => u32::MAX as usize,
+    };
+    if bounds.0 > max || bounds.1 > max {
+        return Err(format!(
+            "{path} cannot hold the {}x{} pixels of the frame, at most {max} a side",
+            bounds.0, bounds.1
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn create_parent_dir(path: &str) -> std::io::Result<()> {
+    match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
+
+/// Writes 8 bit RGB `pixels` as a PNG file.
+pub fn write_png(path: &str, pixels: &[u8], bounds: (usize, usize)) -> std::io::Result<()> {
+    create_parent_dir(path)?;
+    encode_png(path, pixels, bounds, ColorType::RGB(8))
+}
+
+fn encode_png(
+    path: &str,
+    data: &[u8],
+    (width, height): (usize, usize),
+    color: ColorType,
+) -> std::io::Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    PNGEncoder::new(&mut output).encode(data, width as u32, height as u32, color)?;
+    output.flush()
+}
+
+/// Uncompressed 24 bit BMP, whose rows are stored from the bottom and padded
+/// to multiples of 4 bytes.
+fn write_bmp(path: &str, pixels: &[u8], (width, height): (usize, usize)) -> std::io::Result<()> {
+    let row_size = (3 * width).div_ceil(4) * 4;
+    let image_size = (row_size * height) as u32;
+    let mut output = BufWriter::new(File::create(path)?);
+
+    // File header, then the 40 byte BITMAPINFOHEADER
+    output.write_all(b"BM")?;
+    output.write_all(&(54 + image_size).to_le_bytes())?;
+    output.write_all(&[0; 4])?;
+    output.write_all(&54u32.to_le_bytes())?;
+    output.write_all(&40u32.to_le_bytes())?;
+    output.write_all(&(width as i32).to_le_bytes())?;
+    output.write_all(&(height as i32).to_le_bytes())?;
+    output.write_all(&1u16.to_le_bytes())?;
+    output.write_all(&24u16.to_le_bytes())?;
+    output.write_all(&0u32.to_le_bytes())?;
+    output.write_all(&image_size.to_le_bytes())?;
+    // 72 dpi, and no palette
+    output.write_all(&2835i32.to_le_bytes())?;
+    output.write_all(&2835i32.to_le_bytes())?;
+    output.write_all(&[0; 8])?;
+
+    let padding = vec![0; row_size - 3 * width];
+    for row in pixels.chunks(3 * width).take(height).rev() {
+        for rgb in row.chunks(3) {
+            output.write_all(&[rgb[2], rgb[1], rgb[0]])?;
+        }
+        output.write_all(&padding)?;
+    }
+    output.flush()
+}
+
+/// Uncompressed true color TGA, stored from the top row.
+fn write_tga(path: &str, pixels: &[u8], (width, height): (usize, usize)) -> std::io::Result<()> {
+    let size = |v: usize| {
+        u16::try_from(v).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("TGA images are at most 65535 pixels a side, not {v}"),
+            )
+        })
+    };
+    let mut header = [0u8; 18];
+    header[2] = 2;
+    header[12..14].copy_from_slice(&size(width)?.to_le_bytes());
+    header[14..16].copy_from_slice(&size(height)?.to_le_bytes());
+    let mut output = BufWriter::new(File::create(path)?);
+    header[16] = 24;
+    // Origin at the top left
+    header[17] = 0x20;
+    output.write_all(&header)?;
+    for rgb in pixels.chunks(3) {
+        output.write_all(&[rgb[2], rgb[1], rgb[0]])?;
+    }
+    output.flush()
+}
+
+/// Writes a linear floating point image, given row by row from the top, in
+/// the format matching the extension of `path`: OpenEXR (`.exr`), Radiance
+/// (`.hdr`) or portable float map (`.pfm`).
+pub fn write_hdr_image(
+    path: &str,
+    pixels: &[Color],
+    bounds: (usize, usize),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = hdr_format(path)?;
+    create_parent_dir(path)?;
+    write_float_image(path, format, pixels, bounds)
+}
+
+/// Pastes the `pixels` of `crop` into the image of `bounds` pixels written at
+/// `path` by `write_hdr_image`.
+pub fn composite_hdr_image(
+    path: &str,
+    pixels: &[Color],
+    crop: &Tile,
+    bounds: (usize, usize),
+) -> Result<(), Box<dyn std::error::Error>> {
+    composite_float_image(path, hdr_format(path)?, pixels, crop, bounds)
+}
+
+fn hdr_format(path: &str) -> Result<OutputFormat, String> {
+    let format = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(OutputFormat::from_name);
+    match format {
+        Some(format @ (OutputFormat::Exr | OutputFormat::Hdr | OutputFormat::Pfm)) => Ok(format),
+        _ => Err(format!("unsupported HDR image format: {path}")),
+    }
+}
+
+fn composite_float_image(
+    path: &str,
+    format: OutputFormat,
+    pixels: &[Color],
+    crop: &Tile,
+    bounds: (usize, usize),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height, mut image) = read_float_image(path, format)?;
+    check_size(path, (width, height), bounds)?;
+    crop.paste(&mut image, pixels, width, 1);
+    write_float_image(path, format, &image, bounds)
+}
+
+/// Reads a linear floating point image in one of the formats written by
+/// `write_float_image`, returning its size and pixels row by row.
+pub fn read_float_image(
+    path: &str,
+    format: OutputFormat,
+) -> Result<(usize, usize, Vec<Color>), Box<dyn std::error::Error>> {
+    match format {
+        OutputFormat::Exr => {
+            let image = exr::prelude::read_first_rgba_layer_from_file(
+                path,
+                |resolution, _channels| {
+                    (
+                        resolution.width(),
+                        vec![Color::default(); resolution.width() * resolution.height()],
+                    )
+                },
+                |(width, pixels), position, (r, g, b, _a): (f32, f32, f32, f32)| {
+                    pixels[position.y() * *width + position.x()] =
+                        Color::new(r as f64, g as f64, b as f64);
+                },
+            )?;
+            let (width, pixels) = image.layer_data.channel_data.pixels;
+            Ok((width, pixels.len() / width, pixels))
+        }
+        OutputFormat::Pfm => read_pfm(path),
+        _ => {
+            let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()?
+                .into_iter()
+                .map(|p| Color::new(p.data[0] as f64, p.data[1] as f64, p.data[2] as f64))
+                .collect();
+            Ok((metadata.width as usize, metadata.height as usize, pixels))
+        }
+    }
+}
+
+pub fn write_float_image(
+    path: &str,
+    format: OutputFormat,
+    pixels: &[Color],
+    (width, height): (usize, usize),
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        OutputFormat::Exr => {
+            exr::prelude::write_rgb_file(path, width, height, |x, y| {
+                let c = pixels[y * width + x];
+                (c.x() as f32, c.y() as f32, c.z() as f32)
+            })?;
+        }
+        OutputFormat::Hdr => {
+            let data: Vec<Rgb<f32>> = pixels
+                .iter()
+                .map(|c| Rgb {
+                    data: [c.x() as f32, c.y() as f32, c.z() as f32],
+                })
+                .collect();
+            let mut output = BufWriter::new(File::create(path)?);
+            HDREncoder::new(&mut output).encode(&data, width, height)?;
+            output.flush()?;
+        }
+        _ => write_pfm(path, pixels, (width, height))?,
+    }
+    Ok(())
+}
+
+/// Single layer EXR holding the named `channels`, each a plane of floats
+/// row by row. Names such as `albedo.R` group channels into layers.
+pub fn write_exr_channels(
+    path: &str,
+    bounds: (usize, usize),
+    channels: Vec<(String, Vec<f32>)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use exr::prelude::*;
+
+    create_parent_dir(path)?;
+    let channels = channels
+        .into_iter()
+        .map(|(name, samples)| AnyChannel::new(name.as_str(), FlatSamples::F32(samples)))
+        .collect();
+    let layer = Layer::new(
+        bounds,
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer).write().to_file(path)?;
+    Ok(())
+}
+
+/// Path of a file next to `path`, named after it with `suffix` appended and
+/// the given `extension`, e.g. `data/render_depth.exr` for `data/render.png`.
+pub fn sibling_path(path: &str, suffix: &str, extension: &str) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("render");
+    path.with_file_name(format!("{stem}_{suffix}.{extension}"))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// `path` numbered for `frame` of an animation, like `render_0001.png`.
+pub fn numbered_path(path: &str, frame: u32) -> String {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some(extension) => sibling_path(path, &format!("{frame:04}"), extension),
+        None => format!("{path}_{frame:04}"),
+    }
+}
+
+/// Portable float map: a short text header followed by little endian floats,
+/// with the rows stored from the bottom of the image.
+fn write_pfm(path: &str, pixels: &[Color], (width, height): (usize, usize)) -> std::io::Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    // A negative scale marks the data as little endian
+    write!(output, "PF\n{width} {height}\n-1.0\n")?;
+    for row in pixels.chunks(width).take(height).rev() {
+        for c in row {
+            for v in [c.x(), c.y(), c.z()] {
+                output.write_all(&(v as f32).to_le_bytes())?;
+            }
+        }
+    }
+    output.flush()
+}
+
+/// Reads a color portable float map, in either byte order.
+fn read_pfm(path: &str) -> Result<(usize, usize, Vec<Color>), Box<dyn std::error::Error>> {
+    let mut input = BufReader::new(File::open(path)?);
+    // The kind, the size and the scale, on three lines
+    let mut header = Vec::new();
+    while header.len() < 4 {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(format!("{path}: truncated PFM header").into());
+        }
+        header.extend(line.split_whitespace().map(String::from));
+    }
+    if header[0] != "PF" {
+        return Err(format!("{path} is not a color PFM").into());
+    }
+    let (width, height): (usize, usize) = (header[1].parse()?, header[2].parse()?);
+    // A negative scale marks the data as little endian
+    let little_endian = header[3].parse::<f64>()? < 0.0;
+
+    let mut data = vec![0; width * height * 12];
+    input.read_exact(&mut data)?;
+    let value = |b: &[u8]| {
+        let b = [b[0], b[1], b[2], b[3]];
+        let v = if little_endian {
+            f32::from_le_bytes(b)
+        } else {
+            f32::from_be_bytes(b)
+        };
+        v as f64
+    };
+    // Rows are stored from the bottom
+    let pixels = data
+        .chunks_exact(12 * width.max(1))
+        .rev()
+        .flat_map(|row| row.chunks_exact(12))
+        .map(|c| Color::new(value(&c[0..4]), value(&c[4..8]), value(&c[8..12])))
+        .collect();
+    Ok((width, height, pixels))
+}
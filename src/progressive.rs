@@ -0,0 +1,279 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adaptive::PixelStats,
+    aov::Aov,
+    filter::Filter,
+    output::{sibling_path, Output},
+    renderer::{add_samples, sample_counts, Accumulation},
+    sampler::{hash, SamplerKind},
+    state::State,
+};
+
+/// Settings of progressive rendering, which adds samples to every pixel in
+/// passes and saves the render along the way so that it can be resumed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Progressive {
+    /// Samples added to every pixel by each pass
+    pub pass_samples: u32,
+    /// Samples per pixel at which rendering stops, `samples_per_pixel` or
+    /// the `max_samples` of adaptive sampling by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_samples: Option<u32>,
+    /// Seconds after which rendering stops at the end of the current pass
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_limit: Option<f64>,
+    /// File the render is saved to, `render_checkpoint.bin` next to the
+    /// output by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<String>,
+    /// Seconds between two checkpoints, which are also written when the
+    /// render stops
+    pub checkpoint_interval: f64,
+    /// Continues the render saved in the checkpoint instead of starting over
+    pub resume: bool,
+}
+
+impl Default for Progressive {
+    fn default() -> Self {
+        Self {
+            pass_samples: 4,
+            target_samples: None,
+            time_limit: None,
+            checkpoint: None,
+            checkpoint_interval: 60.0,
+            resume: false,
+        }
+    }
+}
+
+impl Progressive {
+    pub fn checkpoint_path(&self, output: &Output) -> String {
+        self.checkpoint
+            .clone()
+            .unwrap_or_else(|| sibling_path(&output.path, "checkpoint", "bin"))
+    }
+}
+
+/// Path traces the image of `state` pass after pass until every pixel has
+/// the target number of samples or the time is up.
+pub fn render(state: &State, settings: &Progressive) -> Accumulation {
+    let path = settings.checkpoint_path(&state.output);
+    let mut accumulation = if settings.resume {
+        match read_checkpoint(&path, state) {
+            Ok(accumulation) => accumulation,
+            Err(e) => panic!("error resuming from {path}: {e}"),
+        }
+    } else {
+        Accumulation::new(state)
+    };
+
+    let target = settings.target_samples.unwrap_or(sample_counts(state).1);
+    // Pixels left behind by adaptive sampling have converged, so the passes
+    // go on from the most sampled ones
+    let mut reached = accumulation
+        .stats
+        .iter()
+        .map(|s| s.count())
+        .max()
+        .unwrap_or(0);
+    if settings.resume {
+        println!("Resuming from {path} at {reached} samples per pixel");
+    }
+
+    let start = Instant::now();
+    let mut saved = Instant::now();
+    while reached < target {
+        let until = (reached + settings.pass_samples.max(1)).min(target);
+        let before = accumulation.total_samples();
+        add_samples(
+            state,
+            &mut accumulation,
+            until,
+            &format!(" [{until}/{target} spp]"),
+        );
+        reached = until;
+        if accumulation.total_samples() == before {
+            // Every pixel converged
+            break;
+        }
+
+        let out_of_time = settings
+            .time_limit
+            .is_some_and(|limit| start.elapsed().as_secs_f64() >= limit);
+        if out_of_time {
+            println!("Time limit reached at {reached} samples per pixel");
+            break;
+        }
+        if reached < target && saved.elapsed().as_secs_f64() >= settings.checkpoint_interval {
+            save(&path, state, &accumulation);
+            saved = Instant::now();
+        }
+    }
+    save(&path, state, &accumulation);
+
+    accumulation
+}
+
+/// Writes a checkpoint, only warning on failure so that the render goes on.
+fn save(path: &str, state: &State, accumulation: &Accumulation) {
+    match write_checkpoint(path, state, accumulation) {
+        Ok(()) => println!("Checkpoint saved to {path}"),
+        Err(e) => eprintln!("error writing the checkpoint {path}: {e}"),
+    }
+}
+
+const MAGIC: &str = "ray_tracer checkpoint 1";
+
+/// Settings the saved buffers depend on, which must not change between the
+/// render and its resumption.
+///
+/// The random numbers of a sample only depend on the sampler, its batch, the
+/// seed, the pixel and the index of the sample, so along with the number of
+/// samples of every pixel they are the whole state of the random numbers.
+/// The rest of the scene is compared through a hash.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct CheckpointHeader {
+    width: usize,
+    height: usize,
+    sampler: SamplerKind,
+    batch: u32,
+    seed: u64,
+    filter: Filter,
+    passes: Vec<Aov>,
+    scene: u64,
+}
+
+impl CheckpointHeader {
+    fn new(state: &State) -> Self {
+        Self {
+            width: state.width.unwrap() as usize,
+            height: state.height as usize,
+            sampler: state.sampler,
+            batch: sample_counts(state).0,
+            seed: state.seed,
+            filter: state.filter,
+            passes: state.aov_passes(),
+            scene: scene_hash(state),
+        }
+    }
+}
+
+/// Hash of the state as JSON, leaving out the settings of progressive
+/// rendering and the files written, which may change when resuming.
+fn scene_hash(state: &State) -> u64 {
+    let mut scene = serde_json::to_value(state).expect("error serializing the state");
+    if let Some(fields) = scene.as_object_mut() {
+        for key in ["progressive", "output", "hdr_output"] {
+            fields.remove(key);
+        }
+        if let Some(adaptive) = fields.get_mut("adaptive").and_then(|a| a.as_object_mut()) {
+            adaptive.remove("heatmap");
+        }
+    }
+    let words: Vec<u64> = scene
+        .to_string()
+        .as_bytes()
+        .chunks(8)
+        .map(|chunk| {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(bytes)
+        })
+        .collect();
+    hash(&words)
+}
+
+/// Saves the film, passes and statistics of every pixel: a line naming the
+/// format, the header as JSON on a line, then little endian doubles. The
+/// file is written aside and renamed, so a crash leaves the previous
+/// checkpoint whole.
+pub fn write_checkpoint(path: &str, state: &State, accumulation: &Accumulation) -> io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temporary = format!("{path}.tmp");
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    writeln!(writer, "{MAGIC}")?;
+    writeln!(
+        writer,
+        "{}",
+        serde_json::to_string(&CheckpointHeader::new(state))?
+    )?;
+    let stats = accumulation.stats.iter().flat_map(|s| s.raw());
+    for value in accumulation
+        .film
+        .raw()
+        .into_iter()
+        .chain(accumulation.aovs.raw())
+        .chain(stats)
+    {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(temporary, path)
+}
+
+/// Loads a checkpoint written by `write_checkpoint` for the same settings.
+pub fn read_checkpoint(path: &str, state: &State) -> io::Result<Accumulation> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != MAGIC {
+        return Err(invalid("not a checkpoint".to_string()));
+    }
+    line.clear();
+    reader.read_line(&mut line)?;
+    let header: CheckpointHeader = serde_json::from_str(&line)?;
+    let expected = CheckpointHeader::new(state);
+    if header != expected {
+        let (found, expected) = (
+            serde_json::to_value(&header)?,
+            serde_json::to_value(&expected)?,
+        );
+        let keys = [
+            "width", "height", "sampler", "batch", "seed", "filter", "passes", "scene",
+        ];
+        let differing: Vec<&str> = keys
+            .into_iter()
+            .filter(|&key| found[key] != expected[key])
+            .collect();
+        return Err(invalid(format!(
+            "the {} of the checkpoint differ from the state",
+            differing.join(", ")
+        )));
+    }
+
+    let mut read = |len: usize| -> io::Result<Vec<f64>> {
+        let mut bytes = vec![0; len * 8];
+        reader.read_exact(&mut bytes)?;
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    };
+    let pixels = header.width * header.height;
+    let mut accumulation = Accumulation::new(state);
+    accumulation.film.load_raw(&read(4 * pixels)?);
+    accumulation
+        .aovs
+        .load_raw(&read(4 * pixels * header.passes.len())?);
+    accumulation.stats = read(3 * pixels)?
+        .chunks_exact(3)
+        .map(|s| PixelStats::from_raw([s[0], s[1], s[2]]))
+        .collect();
+
+    Ok(accumulation)
+}
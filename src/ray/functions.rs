@@ -4,7 +4,12 @@ use super::Ray;
 
 impl Ray {
     pub fn new(origin: Point3, dir: Vec3) -> Self {
-        Self { origin, dir }
+        Self::with_time(origin, dir, 0.0)
+    }
+
+    /// Ray leaving `origin` at `time`, measured in frames.
+    pub fn with_time(origin: Point3, dir: Vec3, time: f64) -> Self {
+        Self { origin, dir, time }
     }
 
     pub fn at(&self, t: f64) -> Point3 {
@@ -18,4 +23,8 @@ impl Ray {
     pub fn dir(&self) -> Vec3 {
         self.dir
     }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
 }
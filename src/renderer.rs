@@ -1,180 +1,547 @@
-use std::{f64::INFINITY, fs::File, time::Instant};
+use std::{f64::consts::PI, time::Instant};
 
 use crate::{
-    hittable::{hit_world, sphere::Sphere},
+    adaptive::{heatmap_color, PixelStats},
+    aov::{Aov, AovFilm, AovSample},
+    denoise::{Denoiser, Features},
+    distributed,
+    film::Film,
+    hittable::{hit_world, hit_world_index, sphere::Sphere, HitRecord},
+    light::{Illuminate, LightSample, LightSource},
     material::Scatterable,
+    mlt::{self, MetropolisConfig},
+    output::{composite_hdr_image, write_hdr_image, write_png},
+    progressive,
     ray::Ray,
+    sampler::Sampler,
     state::State,
-    utils::{clamp, random_float},
-    vec3::{functions::dot, Point3},
+    tile::Tile,
+    vec3::{
+        functions::{dot, luminance},
+        Color, Point3,
+    },
 };
-use image::{png::PNGEncoder, ColorType};
 use indicatif::{ProgressBar, ProgressStyle};
-use palette::Pixel;
 use palette::Srgb;
-use rand::Rng;
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
 
-pub fn write_image(pixels: &[u8], bounds: (usize, usize)) -> Result<(), std::io::Error> {
-    let output = File::create("data/render.png")?;
-    let encoder = PNGEncoder::new(output);
-    let _err = encoder.encode(pixels, bounds.0 as u32, bounds.1 as u32, ColorType::RGB(8));
-    Ok(())
+/// Light transport algorithm used to render the scene.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Integrator {
+    #[default]
+    PathTracer,
+    Metropolis(MetropolisConfig),
 }
 
-pub fn render(state: State) {
+pub fn render(state: &State) {
     let image_width = state.width.unwrap() as usize;
     let image_height = state.height as usize;
-    let mut pixels = vec![0; image_height * image_width * 3];
-    let bands: Vec<(usize, &mut [u8])> = pixels.chunks_mut(image_width * 3).enumerate().collect();
+    let frame = (image_width, image_height);
+    let crop = state.crop_window();
+    // Size of the images written, which only hold the crop
+    let bounds = crop.map_or(frame, |crop| (crop.width, crop.height));
+    let keep = |pixels: Vec<Color>| match &crop {
+        Some(crop) => crop.extract(&pixels, image_width),
+        None => pixels,
+    };
 
-    let pb = ProgressBar::new(state.height as u64);
+    let mut aov_images = Vec::new();
+    // Linear exposed radiance of every pixel, kept in floating point until
+    // the images are written
+    let image: Vec<Color> = match &state.integrator {
+        Integrator::PathTracer => {
+            let Accumulation { film, aovs, stats } = render_path_traced(state);
+            let stats = match &crop {
+                Some(crop) => crop.extract(&stats, image_width),
+                None => stats,
+            };
+            if let Some(adaptive) = &state.adaptive {
+                if let Some(path) = &adaptive.heatmap {
+                    let heatmap: Vec<u8> = stats
+                        .iter()
+                        .flat_map(|s| heatmap_color(s.count(), adaptive.max_samples))
+                        .collect();
+                    write_png(path, &heatmap, bounds).expect("error writing heatmap");
+                }
+            }
+            aov_images = aovs
+                .resolve()
+                .into_iter()
+                .map(|(aov, pixels)| (aov, keep(pixels)))
+                .collect();
+            let image = keep(film.resolve());
+            match &state.denoise {
+                Some(denoiser) => denoise(denoiser, &image, &aov_images, &stats, bounds.0),
+                None => image,
+            }
+        }
+        Integrator::Metropolis(config) => {
+            if state.denoise.is_some() {
+                println!("The denoiser only applies to path traced images");
+            }
+            if state.progressive.is_some() {
+                println!("Progressive rendering only applies to path traced images");
+            }
+            if state.distributed.is_some() {
+                println!("Metropolis light transport renders on this machine only");
+            }
+            let image = mlt::render(state, config)
+                .chunks(3)
+                .map(|c| state.camera.expose(Color::new(c[0], c[1], c[2])))
+                .collect();
+            // The chains wander over the whole frame, which is cropped afterwards
+            keep(image)
+        }
+    };
+
+    match (crop, state.crop.is_some_and(|c| c.composite)) {
+        (Some(crop), true) => composite(state, &image, &crop),
+        _ => write(state, &image, &aov_images, bounds),
+    }
+}
+
+/// Writes the image, and its passes, to the outputs of `state`.
+fn write(state: &State, image: &[Color], aov_images: &[(Aov, Vec<Color>)], bounds: (usize, usize)) {
+    if let Some(path) = &state.hdr_output {
+        write_hdr_image(path, image, bounds).expect("error writing HDR image");
+    }
+    if let Err(e) = state.output.write(image, bounds, &state.tone_mapping) {
+        panic!("error writing {}: {e}", state.output.path);
+    }
+    if let Some(settings) = &state.aovs {
+        settings
+            .write(&state.output, image, aov_images, bounds)
+            .expect("error writing the AOVs");
+    }
+}
+
+/// Pastes the rendered `crop` into the images already at the outputs of
+/// `state`.
+fn composite(state: &State, image: &[Color], crop: &Tile) {
+    let frame = (state.width.unwrap() as usize, state.height as usize);
+    if let Some(path) = &state.hdr_output {
+        composite_hdr_image(path, image, crop, frame).expect("error compositing HDR image");
+    }
+    if let Err(e) = state
+        .output
+        .composite(image, crop, frame, &state.tone_mapping)
+    {
+        panic!("error compositing into {}: {e}", state.output.path);
+    }
+    if state.aovs.is_some() {
+        println!("The AOVs are not written when compositing a crop");
+    }
+}
+
+/// Denoises the path traced `image` with the feature passes rendered for it.
+fn denoise(
+    denoiser: &Denoiser,
+    image: &[Color],
+    passes: &[(Aov, Vec<Color>)],
+    stats: &[PixelStats],
+    width: usize,
+) -> Vec<Color> {
+    let pass = |aov: Aov| -> &[Color] {
+        passes
+            .iter()
+            .find(|(a, _)| *a == aov)
+            .map(|(_, pixels)| pixels.as_slice())
+            .expect("denoiser features not rendered")
+    };
+    let variance: Vec<f64> = stats
+        .iter()
+        .map(|s| s.standard_error() * s.standard_error())
+        .collect();
+    let features = Features {
+        albedo: pass(Aov::Albedo),
+        normal: pass(Aov::Normal),
+        depth: pass(Aov::Depth),
+        variance: &variance,
+    };
+    denoiser.denoise(image, &features, width)
+}
+
+/// Samples of a path traced render: the film, the passes and the statistics
+/// of the samples taken in each pixel, to which further samples are added.
+pub struct Accumulation {
+    pub film: Film,
+    pub aovs: AovFilm,
+    pub stats: Vec<PixelStats>,
+}
+
+impl Accumulation {
+    /// Empty buffers for the image and passes of `state`.
+    pub fn new(state: &State) -> Self {
+        let image_width = state.width.unwrap() as usize;
+        let image_height = state.height as usize;
+        Self {
+            film: Film::new(image_width, image_height),
+            aovs: AovFilm::new(&state.aov_passes(), image_width, image_height),
+            stats: vec![PixelStats::default(); image_height * image_width],
+        }
+    }
+
+    /// Number of samples taken in all the pixels.
+    pub fn total_samples(&self) -> u64 {
+        self.stats.iter().map(|s| s.count() as u64).sum()
+    }
+}
+
+/// Samples the sampler spreads evenly, which adaptive sampling takes between
+/// two checks of the noise, and most samples a pixel can take.
+pub fn sample_counts(state: &State) -> (u32, u32) {
+    match &state.adaptive {
+        Some(adaptive) => (adaptive.min_samples, adaptive.max_samples.max(1)),
+        None => (
+            state.samples_per_pixel as u32,
+            (state.samples_per_pixel as u32).max(1),
+        ),
+    }
+}
+
+/// Path traces every line, in passes when rendering progressively or on the
+/// workers of a distributed render.
+fn render_path_traced(state: &State) -> Accumulation {
+    let start = Instant::now();
+    let accumulation = match (&state.distributed, &state.progressive) {
+        (Some(settings), progressive) => {
+            if progressive.is_some() {
+                println!("Distributed renders are not progressive");
+            }
+            distributed::render(state, settings)
+        }
+        (None, Some(settings)) => progressive::render(state, settings),
+        (None, None) => {
+            let mut accumulation = Accumulation::new(state);
+            add_samples(state, &mut accumulation, sample_counts(state).1, "");
+            accumulation
+        }
+    };
+    println!("Time elapsed: {}ms", start.elapsed().as_millis());
+    if state.adaptive.is_some() || state.progressive.is_some() {
+        println!(
+            "Average samples per pixel: {:.1}",
+            accumulation.total_samples() as f64 / accumulation.stats.len() as f64
+        );
+    }
+
+    accumulation
+}
+
+/// Samples every pixel of `accumulation` until it has `until` samples or
+/// adaptive sampling finds it converged, `label` describing the pass.
+pub fn add_samples(state: &State, accumulation: &mut Accumulation, until: u32, label: &str) {
+    let image_width = state.width.unwrap() as usize;
+    let region = sampled_region(state);
+    let mut stats = region.extract(&accumulation.stats, image_width);
+
+    let pb = progress_bar(region.len() as u64);
+    let start = Instant::now();
+    let film = &mut accumulation.film;
+    let aovs = &mut accumulation.aovs;
+    sample_region(state, &region, film, aovs, &mut stats, until, &|tile| {
+        // Progress counts pixels, as tiles on the edges are smaller
+        pb.inc(tile.len() as u64);
+        show_progress(&pb, start, label);
+    });
+    region.paste(&mut accumulation.stats, &stats, image_width, 1);
+    pb.finish_with_message(format!("[Render Complete!]{label}"));
+}
+
+/// Pixels the path tracer samples: the whole frame, or the crop and the
+/// pixels around it whose samples reach into it.
+pub fn sampled_region(state: &State) -> Tile {
+    let bounds = (state.width.unwrap() as usize, state.height as usize);
+    match state.crop_window() {
+        Some(crop) => {
+            let (columns, rows) = Film::reached(bounds, &crop, &state.filter);
+            Tile {
+                x0: columns.start,
+                y0: rows.start,
+                width: columns.len(),
+                height: rows.len(),
+            }
+        }
+        None => Tile::frame(bounds.0, bounds.1),
+    }
+}
+
+/// Progress bar over `total` pixels.
+pub fn progress_bar(total: u64) -> ProgressBar {
+    let pb = ProgressBar::new(total);
     let sty = ProgressStyle::with_template("[{elapsed_precise}] {prefix} {bar:40.cyan/blue} {msg}")
         .unwrap();
     pb.set_style(sty);
+    pb
+}
 
-    let start = Instant::now();
-    bands.into_par_iter().for_each(|(i, band)| {
-        let a = pb.position();
-        pb.inc(1);
+/// Shows the time left and the share of the pixels done since `start`.
+pub fn show_progress(pb: &ProgressBar, start: Instant, label: &str) {
+    let (done, total) = (pb.position(), pb.length().unwrap_or(0));
+    if done == 0 || total == 0 {
+        return;
+    }
+    let time_left = start.elapsed().as_millis() * (total - done) as u128 / done as u128;
+    pb.set_prefix(format!("[Remaining: {}ms]", time_left));
+    pb.set_message(format!("{}%{label}", done * 100 / total));
+}
+
+/// Samples the pixels of `region`, whose statistics `stats` holds row by
+/// row, until they have `until` samples, splatting them into `film` and
+/// `aovs`, which must cover the pixels reached by the filter. `done` is
+/// called after every tile.
+pub fn sample_region(
+    state: &State,
+    region: &Tile,
+    film: &mut Film,
+    aovs: &mut AovFilm,
+    stats: &mut [PixelStats],
+    until: u32,
+    done: &(dyn Fn(&Tile) + Sync),
+) {
+    let bounds = (state.width.unwrap() as usize, state.height as usize);
+    let passes = &state.aov_passes();
+    let index = |(x, y): (usize, usize)| (y - region.y0) * region.width + x - region.x0;
 
-        if a != 0 {
-            let elapsed = start.elapsed().as_millis();
-            let time_per_op = elapsed / a as u128;
+    // Tiles are rendered in parallel on windows of their own, idle threads
+    // stealing the remaining ones, then added to the film in order so that
+    // the result does not depend on the scheduling
+    let tiles = state.tiles.split_region(region);
+    for batch in tiles.chunks(rayon::current_num_threads() * 16) {
+        let windows: Vec<_> = batch
+            .par_iter()
+            .map(|tile| {
+                let mut window = Film::for_tile(bounds, tile, &state.filter);
+                let mut aov_window = AovFilm::for_tile(passes, bounds, tile, &state.filter);
+                let mut tile_stats: Vec<PixelStats> =
+                    tile.pixels().map(|pixel| stats[index(pixel)]).collect();
+                continue_tile(
+                    &mut window,
+                    &mut aov_window,
+                    state,
+                    tile,
+                    &mut tile_stats,
+                    until,
+                );
+                done(tile);
+                (window, aov_window, tile_stats)
+            })
+            .collect();
 
-            let time_left = time_per_op * (state.height as u64 - a) as u128;
-            pb.set_prefix(format!("[Remaining: {}ms]", time_left));
+        for (tile, (window, aov_window, tile_stats)) in batch.iter().zip(windows) {
+            film.merge(&window);
+            aovs.merge(&aov_window);
+            for (pixel, pixel_stats) in tile.pixels().zip(tile_stats) {
+                stats[index(pixel)] = pixel_stats;
+            }
         }
+    }
+}
 
-        pb.set_message(format!("{}%", a * 100 / state.height as u64));
-        render_line(band, &state, i);
-    });
-    pb.finish_with_message("[Render Complete!]");
-    println!("Time elapsed: {}ms", start.elapsed().as_millis());
+/// Splats the samples of line `y` into `film` and their passes into `aovs`,
+/// which must cover the rows reached by the filter, returning the statistics
+/// of the samples taken in each pixel.
+pub fn render_line(
+    film: &mut Film,
+    aovs: &mut AovFilm,
+    state: &State,
+    y: usize,
+) -> Vec<PixelStats> {
+    let tile = Tile::row(film.width(), y);
+    let mut stats = vec![PixelStats::default(); tile.len()];
+    continue_tile(film, aovs, state, &tile, &mut stats, sample_counts(state).1);
+    stats
+}
 
-    write_image(&pixels, (image_width, image_height)).expect("error writing image");
+/// Adds samples to the pixels of `tile`, which already took the ones counted
+/// in `stats`, row by row, until they have `until` samples. `film` and `aovs`
+/// must cover the pixels reached by the filter.
+pub fn continue_tile(
+    film: &mut Film,
+    aovs: &mut AovFilm,
+    state: &State,
+    tile: &Tile,
+    stats: &mut [PixelStats],
+    until: u32,
+) {
+    // Adaptive sampling stops after a whole batch, so the samples are spread
+    // evenly over each batch. The samples of a pixel only depend on their
+    // index, so continuing a tile gives the same ones as rendering it at once
+    let (batch, _) = sample_counts(state);
+    let mut sampler = state.sampler.create(batch, state.seed);
+
+    for (pixel, stats) in tile.pixels().zip(stats.iter_mut()) {
+        let sampler = sampler.as_mut();
+        sample_pixel(film, aovs, state, sampler, pixel, stats, until);
+    }
 }
 
-pub fn render_line(pixels: &mut [u8], state: &State, y: usize) {
+/// Splats exposed samples of pixel (`x`, `y`) into `film` until it has
+/// `max_samples`, stopping early when adaptive sampling finds it converged,
+/// and adds them to its statistics.
+fn sample_pixel(
+    film: &mut Film,
+    aovs: &mut AovFilm,
+    state: &State,
+    sampler: &mut dyn Sampler,
+    (x, y): (usize, usize),
+    stats: &mut PixelStats,
+    max_samples: u32,
+) {
     let bounds = (state.width.unwrap() as usize, state.height as usize);
 
-    for x in 0..bounds.0 {
-        let mut pixel_colors: Vec<f32> = vec![0.0; 3];
-        for _s in 0..state.samples_per_pixel {
-            let u = (x as f64 + random_float()) / (bounds.0 - 1) as f64;
-            let v = (bounds.1 as f64 - (y as f64 + random_float())) / (bounds.1 - 1) as f64;
-            let r = state.camera.get_ray(u, v);
-            let c = ray_color(
-                r,
-                &state.entities_vec,
-                state.lights.as_ref(),
-                state.max_depth,
-                state.max_depth,
-            );
-            pixel_colors[0] += c.red;
-            pixel_colors[1] += c.green;
-            pixel_colors[2] += c.blue
+    while stats.count() < max_samples {
+        if let Some(adaptive) = &state.adaptive {
+            if adaptive.converged(stats) {
+                break;
+            }
         }
-        let scale = 1.0 / state.samples_per_pixel as f32;
+        sampler.start_pixel_sample((x as u32, y as u32), stats.count());
+        let (dx, dy) = sampler.get_2d();
+        let u = (x as f64 + dx) / (bounds.0 - 1) as f64;
+        let v = (bounds.1 as f64 - (y as f64 + dy)) / (bounds.1 - 1) as f64;
+        let (c, aov_sample) = match state.camera.get_ray(u, v, sampler) {
+            Some(r) => trace_path(r, state, sampler),
+            None => (Color::default(), AovSample::default()),
+        };
 
-        let color = Srgb::new(
-            (scale * pixel_colors[0]).sqrt(),
-            (scale * pixel_colors[1]).sqrt(),
-            (scale * pixel_colors[2]).sqrt(),
-        );
+        let exposed = state.camera.expose(c);
+        let position = (x as f64 + dx, y as f64 + dy);
+        film.splat(position, exposed, &state.filter);
+        aovs.splat(position, (x, y), &aov_sample, &state.filter, |c| {
+            state.camera.expose(c)
+        });
+        stats.add(luminance(exposed));
+    }
+}
 
-        let pixel: [u8; 3] = color.into_format().into_raw();
-        pixels[x * 3] = pixel[0];
-        pixels[x * 3 + 1] = pixel[1];
-        pixels[x * 3 + 2] = pixel[2];
-    }
-}
-
-pub fn ray_color(
-    ray: Ray,
-    world: &Vec<Sphere>,
-    lights_opt: Option<&Vec<Sphere>>,
-    max_depth: i32,
-    depth: i32,
-) -> Srgb {
-    if depth <= 0 {
-        return Srgb::new(0., 0., 0.);
-    }
-
-    let hit = hit_world(world, &ray, 0.0001, INFINITY);
-
-    match hit {
-        Some(hit_record) => {
-            let mut light_red: f64 = 0.0;
-            let mut light_green = 0.0;
-            let mut light_blue = 0.0;
-            let prob = 0.1;
-            let scattered = hit_record.material.scatter(&ray, &hit_record);
-
-            match scattered {
-                Some((scattered_ray, albedo)) => {
-                    match lights_opt {
-                        Some(lights) => {
-                            if !lights.is_empty()
-                                && depth > (max_depth - 2)
-                                && rand::thread_rng().gen::<f64>()
-                                    > (1.0 - lights.len() as f64 * prob)
-                            {
-                                for light in lights {
-                                    let light_ray =
-                                        Ray::new(hit_record.p, light.center - hit_record.p);
-                                    let target_color =
-                                        ray_color(light_ray, world, Some(lights), 2, 1);
-                                    light_red += (albedo.red * target_color.red) as f64;
-                                    light_green += (albedo.green * target_color.green) as f64;
-                                    light_blue += (albedo.blue * target_color.blue) as f64;
-                                }
-                                light_red /= lights.len() as f64;
-                                light_green /= lights.len() as f64;
-                                light_blue /= lights.len() as f64;
-                            }
-                        }
-                        None => {}
-                    }
+/// Traces a path from `ray`, adding the direct light sampled at every diffuse
+/// vertex and the emission found by camera and specular rays.
+pub fn ray_color(ray: Ray, state: &State, sampler: &mut dyn Sampler) -> Srgb {
+    trace_path(ray, state, sampler).0.into()
+}
+
+/// Radiance carried by the path traced from `ray`, as `ray_color`, and the
+/// values of the passes it gives.
+pub fn trace_path(ray: Ray, state: &State, sampler: &mut dyn Sampler) -> (Color, AovSample) {
+    let world = &state.entities_vec;
+    let lights: &[LightSource] = state.lights.as_deref().unwrap_or_default();
 
-                    match scattered_ray {
-                        Some(sr) => {
-                            let target_color =
-                                ray_color(sr, world, lights_opt, max_depth, depth - 1);
-                            Srgb::new(
-                                clamp(
-                                    (light_red as f32 + albedo.red * target_color.red) as f64,
-                                    0.,
-                                    1.,
-                                ) as f32,
-                                clamp(
-                                    (light_green as f32 + albedo.green * target_color.green) as f64,
-                                    0.,
-                                    1.,
-                                ) as f32,
-                                clamp(
-                                    (light_blue as f32 + albedo.blue * target_color.blue) as f64,
-                                    0.,
-                                    1.,
-                                ) as f32,
-                            )
-                        }
-                        None => albedo,
+    let mut color = Color::default();
+    let mut aovs = AovSample::default();
+    let mut throughput = Color::new(1., 1., 1.);
+    let mut ray = ray;
+    // Emitters reached right after a diffuse bounce were already sampled directly
+    let mut count_emitted = true;
+    // Kind of the first surface, which splits the light passes
+    let mut diffuse_first = false;
+
+    for bounces in 0..state.max_depth.max(0) as u32 {
+        let hit = hit_world_index(world, &ray, 0.0001, f64::INFINITY);
+        if count_emitted {
+            let t_max = hit.as_ref().map_or(f64::INFINITY, |(_, h)| h.t);
+            if let Some((_t, radiance)) = state.emitters.hit(lights, &ray, t_max) {
+                color += throughput * radiance;
+                aovs.add_light(bounces, diffuse_first, throughput * radiance);
+            }
+        }
+
+        let hit_record = match hit {
+            Some((object, hit_record)) => {
+                if bounces == 0 {
+                    aovs.set_first_hit(&ray, &hit_record, object, state.aovs.as_ref());
+                }
+                hit_record
+            }
+            None => {
+                if count_emitted || !state.background.is_sampled() {
+                    let light = throughput * state.background.radiance(ray.dir());
+                    color += light;
+                    aovs.add_light(bounces, diffuse_first, light);
+                }
+                break;
+            }
+        };
+
+        match hit_record.material.scatter(&ray, &hit_record, sampler) {
+            Some((Some(scattered_ray), attenuation)) => {
+                let diffuse_albedo = hit_record.material.diffuse_albedo();
+                if bounces == 0 {
+                    diffuse_first = diffuse_albedo.is_some();
+                }
+                match diffuse_albedo {
+                    Some(albedo) => {
+                        let direct = direct_light(&hit_record, state, sampler);
+                        let light = throughput * Color::from(albedo) * direct / PI;
+                        color += light;
+                        aovs.add_light(bounces + 1, diffuse_first, light);
+                        count_emitted = false;
                     }
+                    None => count_emitted = true,
                 }
-                None => Srgb::new(0., 0., 0.),
+                throughput = throughput * Color::from(attenuation);
+                ray = scattered_ray;
             }
+            Some((None, emitted)) => {
+                if count_emitted {
+                    color += throughput * Color::from(emitted);
+                    aovs.add_light(bounces, diffuse_first, throughput * Color::from(emitted));
+                }
+                break;
+            }
+            None => break,
         }
-        None => {
-            let t = clamp(0.5 * (ray.dir().unit_vec().y() + 1.0), 0., 1.);
-            let _u: f64 = clamp(0.5 * (ray.dir().unit_vec().x() + 1.0), 0., 1.);
-            Srgb::new(
-                ((1.0 - t) * 1.0 + t * 0.5) as f32,
-                ((1.0 - t) * 1.0 + t * 0.7) as f32,
-                ((1.0 - t) * 1.0 + t * 1.0) as f32,
-            )
+    }
+
+    (color, aovs)
+}
+
+/// Light reaching `hit_record` from one selected light and the background, weighted
+/// by the cosine term and ready to be multiplied by the BRDF.
+fn direct_light(hit_record: &HitRecord, state: &State, sampler: &mut dyn Sampler) -> Color {
+    let lights: &[LightSource] = state.lights.as_deref().unwrap_or_default();
+    let mut total = Color::default();
+
+    // A single light chosen by importance keeps the cost independent of their number
+    if let Some(selector) = &state.light_selector {
+        if let Some((index, pmf)) =
+            selector.sample(hit_record.p, hit_record.normal, sampler.get_1d())
+        {
+            let light = lights[index].at_time(hit_record.time);
+            let sample = light.sample(hit_record.p, sampler);
+            total += unoccluded(hit_record, sample, &state.entities_vec) / pmf;
         }
     }
+    if state.background.is_sampled() {
+        total += unoccluded(
+            hit_record,
+            state.background.sample(hit_record.p, sampler),
+            &state.entities_vec,
+        );
+    }
+
+    total
+}
+
+/// Contribution of a light sample, or black when it lies behind the surface
+/// or something blocks it.
+fn unoccluded(hit_record: &HitRecord, sample: Option<LightSample>, world: &[Sphere]) -> Color {
+    let sample = match sample {
+        Some(sample) => sample,
+        None => return Color::default(),
+    };
+    let cos_theta = dot(hit_record.normal, sample.dir);
+    if cos_theta <= 0.0 {
+        return Color::default();
+    }
+
+    let shadow_ray = Ray::with_time(hit_record.p, sample.dir, hit_record.time);
+    let t_max = sample.distance * (1.0 - 1e-6);
+    match hit_world(world, &shadow_ray, 0.0001, t_max) {
+        Some(_) => Color::default(),
+        None => cos_theta * sample.radiance,
+    }
 }
 
 pub fn hit_sphere(center: Point3, radius: f64, ray: Ray) -> f64 {
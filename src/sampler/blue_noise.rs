@@ -0,0 +1,196 @@
+use std::sync::OnceLock;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    hash,
+    sobol::{from_fixed_point, owen_scramble, sobol_0, sobol_1},
+    Sampler,
+};
+
+/// Side of the tileable blue noise mask.
+const MASK_SIZE: usize = 64;
+/// Standard deviation of the Gaussian measuring how clustered the mask is.
+const MASK_SIGMA: f64 = 1.9;
+
+/// Sobol points shared by every pixel, shifted modulo one by a blue noise
+/// mask (Georgiev and Fajardo). Neighbouring pixels get very different
+/// shifts, pushing the error of the image to high frequencies.
+///
+/// The mask is offset by a different amount in every dimension so that the
+/// dimensions stay uncorrelated.
+#[derive(Debug, Clone)]
+pub struct BlueNoiseSampler {
+    seed: u64,
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u64,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Value of the mask at the current pixel, tiled and offset by `h`.
+    fn shift(&self, h: u64) -> f64 {
+        let x = (self.pixel.0 as usize + h as usize) % MASK_SIZE;
+        let y = (self.pixel.1 as usize + (h >> 32) as usize) % MASK_SIZE;
+        mask()[y * MASK_SIZE + x]
+    }
+
+    fn next_hash(&mut self) -> u64 {
+        let h = hash(&[self.dimension, self.seed]);
+        self.dimension += 1;
+        h
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.pixel = pixel;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let h = self.next_hash();
+        let value = from_fixed_point(owen_scramble(sobol_0(self.sample_index), h));
+        (value + self.shift(hash(&[h]))).fract()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let h = self.next_hash();
+        let x = from_fixed_point(owen_scramble(sobol_0(self.sample_index), h));
+        let y = from_fixed_point(owen_scramble(sobol_1(self.sample_index), h >> 32));
+        (
+            (x + self.shift(hash(&[h, 0]))).fract(),
+            (y + self.shift(hash(&[h, 1]))).fract(),
+        )
+    }
+}
+
+/// Blue noise threshold mask in [0, 1), built once with Ulichney's
+/// void-and-cluster method.
+pub fn mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let n = MASK_SIZE * MASK_SIZE;
+        void_and_cluster(MASK_SIZE, MASK_SIGMA)
+            .into_iter()
+            .map(|rank| (rank as f64 + 0.5) / n as f64)
+            .collect()
+    })
+}
+
+/// Binary pattern on a torus together with the Gaussian blurred density of
+/// its set pixels.
+#[derive(Clone)]
+struct Pattern {
+    size: usize,
+    kernel: Vec<f64>,
+    bits: Vec<bool>,
+    energy: Vec<f64>,
+}
+
+impl Pattern {
+    fn new(size: usize, sigma: f64) -> Self {
+        let wrap = |d: usize| d.min(size - d) as f64;
+        let kernel = (0..size * size)
+            .map(|i| {
+                let (dx, dy) = (wrap(i % size), wrap(i / size));
+                (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+            })
+            .collect();
+
+        Self {
+            size,
+            kernel,
+            bits: vec![false; size * size],
+            energy: vec![0.0; size * size],
+        }
+    }
+
+    fn toggle(&mut self, p: usize) {
+        self.bits[p] = !self.bits[p];
+        let sign = if self.bits[p] { 1.0 } else { -1.0 };
+        let (px, py) = (p % self.size, p / self.size);
+        for (q, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (q % self.size + self.size - px) % self.size;
+            let dy = (q / self.size + self.size - py) % self.size;
+            *energy += sign * self.kernel[dy * self.size + dx];
+        }
+    }
+
+    /// Set pixel with the most set pixels around it.
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |a, b| a > b)
+    }
+
+    /// Unset pixel with the fewest set pixels around it.
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |a, b| a < b)
+    }
+
+    fn extreme(&self, bit: bool, better: impl Fn(f64, f64) -> bool) -> usize {
+        let mut best = None;
+        for (p, &energy) in self.energy.iter().enumerate() {
+            if self.bits[p] == bit && best.is_none_or(|b| better(energy, self.energy[b])) {
+                best = Some(p);
+            }
+        }
+        best.expect("pattern has no pixel of the requested value")
+    }
+}
+
+/// Rank of every pixel in the order the void-and-cluster method sets them.
+fn void_and_cluster(size: usize, sigma: f64) -> Vec<u32> {
+    let n = size * size;
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut pattern = Pattern::new(size, sigma);
+
+    // Random initial pattern, relaxed by moving the tightest cluster to the
+    // largest void until that changes nothing
+    let ones = n / 10;
+    while pattern.bits.iter().filter(|&&b| b).count() < ones {
+        let p = rng.gen_range(0..n);
+        if !pattern.bits[p] {
+            pattern.toggle(p);
+        }
+    }
+    loop {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        if void == cluster {
+            pattern.toggle(cluster);
+            break;
+        }
+        pattern.toggle(void);
+    }
+
+    let mut ranks = vec![0; n];
+
+    // The initial pixels are ranked by removing clusters one at a time
+    let mut shrinking = pattern.clone();
+    for rank in (0..ones).rev() {
+        let cluster = shrinking.tightest_cluster();
+        shrinking.toggle(cluster);
+        ranks[cluster] = rank as u32;
+    }
+
+    // And the others by filling voids, which once past half the pixels is
+    // the same as removing clusters of unset pixels
+    for rank in ones..n {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        ranks[void] = rank as u32;
+    }
+
+    ranks
+}
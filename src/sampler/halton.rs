@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+
+use super::{hash, mix_bits, permutation_element, to_unit, Sampler, ONE_MINUS_EPSILON};
+
+/// Dimensions with a prime base; the ones after them fall back to independent
+/// random numbers.
+const PRIME_COUNT: usize = 1000;
+
+/// Halton sequence, with each pixel scrambling the digits of every dimension
+/// differently so that neighbouring pixels are not correlated.
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.pixel = pixel;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let h = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+            self.seed,
+        ]);
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        match primes().get(dimension) {
+            Some(&base) => owen_scrambled_radical_inverse(base, self.sample_index as u64, h),
+            None => to_unit(hash(&[h, self.sample_index as u64])),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// The first `PRIME_COUNT` primes.
+fn primes() -> &'static [u64] {
+    static PRIMES: OnceLock<Vec<u64>> = OnceLock::new();
+    PRIMES.get_or_init(|| {
+        let mut primes: Vec<u64> = Vec::with_capacity(PRIME_COUNT);
+        let mut n = 2;
+        while primes.len() < PRIME_COUNT {
+            if primes
+                .iter()
+                .take_while(|&&p| p * p <= n)
+                .all(|&p| n % p != 0)
+            {
+                primes.push(n);
+            }
+            n += 1;
+        }
+        primes
+    })
+}
+
+/// Digits of `a` in `base` mirrored around the radix point, each digit being
+/// permuted according to the ones before it.
+pub fn owen_scrambled_radical_inverse(base: u64, mut a: u64, hash: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut value = 0.0;
+    // Digits produced so far, only used to choose the next permutation
+    let mut prefix: u64 = 0;
+
+    // Digits past single precision make no visible difference
+    while inv_base_m * (base - 1) as f64 > f32::EPSILON as f64 {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_hash = mix_bits(hash ^ prefix) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_hash) as u64;
+        prefix = prefix.wrapping_mul(base).wrapping_add(digit);
+        inv_base_m *= inv_base;
+        value += digit as f64 * inv_base_m;
+        a = next;
+    }
+
+    value.min(ONE_MINUS_EPSILON)
+}
@@ -0,0 +1,44 @@
+use super::{hash, to_unit, Sampler};
+
+/// Uniform random numbers with no correlation between samples or dimensions.
+///
+/// They are hashed from the pixel, the sample and the dimension, so a render
+/// does not depend on how pixels are spread over threads.
+#[derive(Debug, Clone)]
+pub struct IndependentSampler {
+    seed: u64,
+    state: u64,
+    dimension: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.state = hash(&[
+            pixel.0 as u64,
+            pixel.1 as u64,
+            sample_index as u64,
+            self.seed,
+        ]);
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let value = to_unit(hash(&[self.state, self.dimension]));
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
@@ -0,0 +1,135 @@
+pub mod blue_noise;
+pub mod halton;
+pub mod independent;
+pub mod sobol;
+pub mod stratified;
+
+use serde::{Deserialize, Serialize};
+
+use self::{
+    blue_noise::BlueNoiseSampler, halton::HaltonSampler, independent::IndependentSampler,
+    sobol::SobolSampler, stratified::StratifiedSampler,
+};
+
+/// Source of the numbers in [0, 1) behind every random decision of a path:
+/// the position in the pixel, the lens and shutter samples, light selection
+/// and sampling, and the scattering of materials.
+///
+/// Samplers other than the independent one spread the samples of a pixel
+/// evenly over each dimension, which lowers the noise at the same number of
+/// samples per pixel.
+pub trait Sampler {
+    /// Restarts the sequence at its first dimension for sample `sample_index`
+    /// of `pixel`.
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// Sampler used by the path tracer, chosen in the state file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SamplerKind {
+    /// Uncorrelated uniform random numbers.
+    Independent,
+    /// Jittered strata, shuffled independently in every dimension.
+    Stratified,
+    /// Owen-scrambled Halton sequence.
+    Halton,
+    /// Owen-scrambled Sobol points, shuffled independently in every dimension.
+    #[default]
+    Sobol,
+    /// Sobol points shared by all pixels, offset by a blue noise mask so that
+    /// the remaining error looks like fine grain instead of blotches.
+    BlueNoise,
+}
+
+impl SamplerKind {
+    /// Sampler spreading `samples_per_pixel` samples evenly. Pixels taking
+    /// more samples get them in further batches of that size, each one as
+    /// well distributed as the first.
+    pub fn create(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler + Send> {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(samples_per_pixel, seed)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+/// Largest value below one, where samples are clamped.
+pub const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// Finalizer of a 64 bit hash, turning close inputs into unrelated outputs.
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        mix_bits(h ^ mix_bits(v.wrapping_add(0x632b_e59b_d9b4_e019)))
+    })
+}
+
+/// Uniform number in [0, 1) made of the high 53 bits of `bits`.
+pub fn to_unit(bits: u64) -> f64 {
+    (bits >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+/// Element `i` of a random permutation of `0..l` chosen by `p`, computed
+/// without storing the permutation (Kensler, "Correlated Multi-Jittered
+/// Sampling").
+pub fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.max(1) - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l.max(1) {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l.max(1)
+}
+
+/// Element `i` of a sequence visiting `0..l`, then `l..2l` and so on, each
+/// block in its own random order chosen by `p`.
+pub fn block_permutation(i: u32, l: u32, p: u64) -> u32 {
+    let l = l.max(1);
+    let block = i / l;
+    let p = if block == 0 {
+        p as u32
+    } else {
+        hash(&[p, block as u64]) as u32
+    };
+    block * l + permutation_element(i % l, l, p)
+}
@@ -0,0 +1,103 @@
+use super::{block_permutation, hash, mix_bits, Sampler, ONE_MINUS_EPSILON};
+
+/// Two dimensional Sobol points, Owen-scrambled and visited in a different
+/// order for every pixel and dimension ("padding"), which keeps the
+/// stratification of each pair of dimensions without any direction table.
+///
+/// The points are best distributed when the number of samples per pixel is a
+/// power of two.
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel,
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Index of the current sample in the shuffled order of the current
+    /// dimension, with the hash seeding its scrambling.
+    fn next_index(&mut self) -> (u32, u64) {
+        let h = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        self.dimension += 1;
+        let index = block_permutation(self.sample_index, self.samples_per_pixel, h);
+        (index, h)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.pixel = pixel;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, h) = self.next_index();
+        from_fixed_point(owen_scramble(sobol_0(index), h))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (index, h) = self.next_index();
+        (
+            from_fixed_point(owen_scramble(sobol_0(index), h)),
+            from_fixed_point(owen_scramble(sobol_1(index), h >> 32)),
+        )
+    }
+}
+
+/// First dimension of the Sobol sequence: the van der Corput sequence in base 2.
+pub fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+/// Second dimension of the Sobol sequence, whose generator matrix is the
+/// Pascal triangle modulo 2 (Kollig and Keller).
+pub fn sobol_1(mut index: u32) -> u32 {
+    let mut v: u32 = 1 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// Nested uniform scrambling: every bit is flipped or not depending on the
+/// bits above it, so the stratification of the points is preserved.
+pub fn owen_scramble(mut v: u32, seed: u64) -> u32 {
+    if seed & 1 != 0 {
+        v ^= 1 << 31;
+    }
+    for b in 1..32 {
+        let mask = u32::MAX << (32 - b);
+        if (mix_bits((v & mask) as u64 ^ seed) as u32) & (1 << b) != 0 {
+            v ^= 1 << (31 - b);
+        }
+    }
+    v
+}
+
+/// Fixed point fraction to a number in [0, 1).
+pub fn from_fixed_point(v: u32) -> f64 {
+    (v as f64 / 4_294_967_296.0).min(ONE_MINUS_EPSILON)
+}
@@ -0,0 +1,74 @@
+use super::{block_permutation, hash, to_unit, Sampler};
+
+/// Jittered stratification of every dimension: the samples of a pixel fall in
+/// distinct strata, visited in a different random order in each dimension.
+///
+/// Two dimensional samples use a grid as square as the sample count allows.
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    /// Columns of the grid of 2D strata
+    x_strata: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        // Largest divisor not above the square root keeps the strata close to square
+        let root = (samples_per_pixel as f64).sqrt() as u32;
+        let x_strata = (1..=root.max(1))
+            .rev()
+            .find(|&d| samples_per_pixel.is_multiple_of(d))
+            .unwrap_or(1);
+
+        Self {
+            samples_per_pixel,
+            x_strata,
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Stratum of the current sample in the current dimension, with the hash
+    /// used to jitter inside it.
+    fn stratum(&mut self) -> (u32, u64) {
+        let h = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        self.dimension += 1;
+        let stratum = block_permutation(self.sample_index, self.samples_per_pixel, h)
+            % self.samples_per_pixel;
+        (stratum, hash(&[h, self.sample_index as u64]))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.pixel = pixel;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (stratum, jitter) = self.stratum();
+        (stratum as f64 + to_unit(jitter)) / self.samples_per_pixel as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (stratum, jitter) = self.stratum();
+        let y_strata = self.samples_per_pixel / self.x_strata;
+        let (x, y) = (stratum % self.x_strata, stratum / self.x_strata);
+        (
+            (x as f64 + to_unit(jitter)) / self.x_strata as f64,
+            (y as f64 + to_unit(hash(&[jitter]))) / y_strata as f64,
+        )
+    }
+}
@@ -0,0 +1,208 @@
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+
+/// Direction uniformly distributed over the unit sphere.
+pub fn uniform_sphere((u1, u2): (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Point uniformly distributed inside the unit ball, `u_radius` choosing its
+/// distance from the center.
+pub fn uniform_ball(u: (f64, f64), u_radius: f64) -> Vec3 {
+    u_radius.cbrt() * uniform_sphere(u)
+}
+
+/// Piecewise-constant 1D distribution built from a tabulated function.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    func_int: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as f64;
+        }
+
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if func_int == 0.0 {
+                i as f64 / n as f64
+            } else {
+                *c / func_int
+            };
+        }
+
+        Self {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.func_int
+    }
+
+    /// Maps `u` in [0, 1) to a point in [0, 1) distributed like the function,
+    /// returning it with its density and the index of its segment.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.count();
+        let offset = self.cdf.partition_point(|&c| c <= u).clamp(1, n) - 1;
+
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+
+        let pdf = if self.func_int > 0.0 {
+            self.func[offset].abs() / self.func_int
+        } else {
+            1.0
+        };
+        ((offset as f64 + du) / n as f64, pdf, offset)
+    }
+}
+
+/// Piecewise-constant 2D distribution over [0, 1)², sampled through the
+/// marginal density of the rows and the conditional density inside each row.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` holds `height` rows of `width` values.
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|c| c.integral()).collect());
+
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Mean of the function over the unit square.
+    pub fn integral(&self) -> f64 {
+        self.marginal.integral()
+    }
+
+    pub fn sample_continuous(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u2);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u1);
+        ((x, y), pdf_x * pdf_y)
+    }
+}
+
+/// Walker's alias method: constant time sampling of a discrete distribution.
+#[derive(Debug, Clone)]
+pub struct AliasTable {
+    bins: Vec<AliasBin>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AliasBin {
+    /// Probability of keeping this bin instead of jumping to its alias
+    q: f64,
+    pmf: f64,
+    alias: usize,
+}
+
+impl AliasTable {
+    /// Builds the table from non-negative weights, which need not sum to one.
+    /// All-zero weights give a uniform distribution.
+    pub fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let sum: f64 = weights.iter().sum();
+        let pmfs: Vec<f64> = if sum > 0.0 {
+            weights.iter().map(|w| w / sum).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+
+        let mut bins: Vec<AliasBin> = pmfs
+            .iter()
+            .map(|&pmf| AliasBin {
+                q: 0.0,
+                pmf,
+                alias: 0,
+            })
+            .collect();
+
+        // Vose's method: pair every under-full bin with an over-full one
+        let mut under = Vec::new();
+        let mut over = Vec::new();
+        for (i, &pmf) in pmfs.iter().enumerate() {
+            let p = pmf * n as f64;
+            if p < 1.0 {
+                under.push((i, p));
+            } else {
+                over.push((i, p));
+            }
+        }
+
+        while let (Some(&(small, p_small)), Some(&(large, p_large))) = (under.last(), over.last())
+        {
+            under.pop();
+            over.pop();
+            bins[small].q = p_small;
+            bins[small].alias = large;
+
+            let excess = p_large + p_small - 1.0;
+            if excess < 1.0 {
+                under.push((large, excess));
+            } else {
+                over.push((large, excess));
+            }
+        }
+        // Rounding leaves bins that are full up to floating point error
+        for (i, _) in under.into_iter().chain(over) {
+            bins[i].q = 1.0;
+            bins[i].alias = i;
+        }
+
+        Self { bins }
+    }
+
+    pub fn count(&self) -> usize {
+        self.bins.len()
+    }
+
+    pub fn pmf(&self, index: usize) -> f64 {
+        self.bins[index].pmf
+    }
+
+    /// Maps `u` in [0, 1) to an index, returned with its probability.
+    pub fn sample(&self, u: f64) -> (usize, f64) {
+        let n = self.bins.len();
+        let scaled = u * n as f64;
+        let offset = (scaled as usize).min(n - 1);
+        let up = scaled - offset as f64;
+
+        let index = if up < self.bins[offset].q {
+            offset
+        } else {
+            self.bins[offset].alias
+        };
+        (index, self.bins[index].pmf)
+    }
+}
@@ -0,0 +1,315 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    background::{uv_to_direction, EnvironmentData},
+    light::{Illuminate, LightSample},
+    sampler::Sampler,
+    utils::deg_to_rad,
+    vec3::{
+        functions::{dot, luminance, orthonormal_basis},
+        Color, Point3, Vec3,
+    },
+};
+
+/// Angular radius of the sun disk seen from the earth, in radians.
+const SUN_ANGULAR_RADIUS: f64 = 0.004_65;
+/// Luminance of the sun outside the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f64 = 2.0e6;
+/// Resolution of the equirectangular table used to importance sample the sky.
+const SKY_TABLE_WIDTH: usize = 128;
+const SKY_TABLE_HEIGHT: usize = 64;
+
+/// Preetham et al. analytic daylight model with a matching sun disk.
+///
+/// `elevation` and `azimuth` place the sun in degrees, the azimuth turning
+/// from -z towards +x. Radiance is the luminance of the model in kcd/m²
+/// multiplied by `intensity`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sky {
+    pub elevation: f64,
+    #[serde(default)]
+    pub azimuth: f64,
+    #[serde(default = "default_turbidity")]
+    pub turbidity: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(skip)]
+    data: Option<Arc<SkyData>>,
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_intensity() -> f64 {
+    0.05
+}
+
+#[derive(Debug)]
+struct SkyData {
+    model: Preetham,
+    sun: SunLight,
+    table: EnvironmentData,
+    /// Probability of sampling the sun rather than the sky dome
+    sun_probability: f64,
+}
+
+impl Sky {
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, intensity: f64) -> Self {
+        Self {
+            elevation,
+            azimuth,
+            turbidity,
+            intensity,
+            data: None,
+        }
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        let elevation = deg_to_rad(self.elevation);
+        let azimuth = deg_to_rad(self.azimuth);
+        Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        )
+    }
+
+    pub fn load(&mut self) {
+        let sun_direction = self.sun_direction();
+        let model = Preetham::new(sun_direction, self.turbidity);
+        let sun = SunLight {
+            direction: sun_direction,
+            radiance: self.intensity * sun_radiance(sun_direction, self.turbidity),
+        };
+
+        let pixels = (0..SKY_TABLE_HEIGHT)
+            .flat_map(|y| (0..SKY_TABLE_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let u = (x as f64 + 0.5) / SKY_TABLE_WIDTH as f64;
+                let v = (y as f64 + 0.5) / SKY_TABLE_HEIGHT as f64;
+                self.intensity * model.radiance(uv_to_direction(u, v, 0.0))
+            })
+            .collect();
+        let table = EnvironmentData::new(SKY_TABLE_WIDTH, SKY_TABLE_HEIGHT, pixels);
+
+        let sun_power = luminance(sun.radiance) * sun.solid_angle();
+        let sky_power = table.power();
+        let sun_probability = if sun_power <= 0.0 {
+            0.0
+        } else if sky_power <= 0.0 {
+            1.0
+        } else {
+            (sun_power / (sun_power + sky_power)).clamp(0.1, 0.9)
+        };
+
+        self.data = Some(Arc::new(SkyData {
+            model,
+            sun,
+            table,
+            sun_probability,
+        }));
+    }
+
+    fn data(&self) -> &SkyData {
+        self.data.as_ref().expect("sky used before being loaded")
+    }
+
+    /// Radiance of the sky dome, without the sun.
+    fn sky_radiance(&self, dir: Vec3) -> Color {
+        self.intensity * self.data().model.radiance(dir)
+    }
+
+    pub fn radiance(&self, dir: Vec3) -> Color {
+        let data = self.data();
+        let sun = data.sun.emitted_towards(dir).unwrap_or_default();
+        self.sky_radiance(dir) + sun
+    }
+}
+
+impl Illuminate for Sky {
+    fn sample(&self, p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let data = self.data();
+
+        if sampler.get_1d() < data.sun_probability {
+            let mut sample = data.sun.sample(p, sampler)?;
+            sample.radiance = sample.radiance / data.sun_probability;
+            return Some(sample);
+        }
+
+        let (dir, _uv, pdf) = data.table.sample_direction(0.0, sampler.get_2d())?;
+        Some(LightSample {
+            dir,
+            distance: f64::INFINITY,
+            radiance: self.sky_radiance(dir) / (pdf * (1.0 - data.sun_probability)),
+        })
+    }
+}
+
+/// Distant disk light of uniform radiance subtending the sun's solid angle.
+#[derive(Debug, Clone, Copy)]
+pub struct SunLight {
+    direction: Vec3,
+    radiance: Color,
+}
+
+impl SunLight {
+    fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos())
+    }
+
+    fn emitted_towards(&self, dir: Vec3) -> Option<Color> {
+        if dot(dir.unit_vec(), self.direction) >= SUN_ANGULAR_RADIUS.cos() {
+            Some(self.radiance)
+        } else {
+            None
+        }
+    }
+}
+
+impl Illuminate for SunLight {
+    fn sample(&self, _p: Point3, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        if self.direction.y() <= 0.0 {
+            return None;
+        }
+
+        // Uniformly sample the cone of directions covered by the disk
+        let cos_theta_max = SUN_ANGULAR_RADIUS.cos();
+        let (u1, u2) = sampler.get_2d();
+        let cos_theta = 1.0 - u1 * (1.0 - cos_theta_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let (u, v) = orthonormal_basis(self.direction);
+
+        Some(LightSample {
+            dir: sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * self.direction,
+            distance: f64::INFINITY,
+            radiance: self.solid_angle() * self.radiance,
+        })
+    }
+}
+
+/// Zenith values and Perez distribution coefficients for luminance Y and
+/// chromaticities x, y.
+#[derive(Debug)]
+struct Preetham {
+    sun_direction: Vec3,
+    zenith: [f64; 3],
+    perez: [[f64; 5]; 3],
+}
+
+impl Preetham {
+    fn new(sun_direction: Vec3, turbidity: f64) -> Self {
+        let t = turbidity;
+        // The model is only defined for a sun above the horizon
+        let theta_s = sun_direction.y().clamp(0.0, 1.0).acos();
+        let theta2 = theta_s * theta_s;
+        let theta3 = theta2 * theta_s;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y_chroma = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta_s + 0.26688);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // Store the zenith values already divided by the Perez function at the zenith
+        let mut zenith = [zenith_y, zenith_x, zenith_y_chroma];
+        for (z, coefficients) in zenith.iter_mut().zip(perez.iter()) {
+            *z /= perez_function(coefficients, 0.0, theta_s);
+        }
+
+        Self {
+            sun_direction: Vec3::new(sun_direction.x(), theta_s.cos(), sun_direction.z())
+                .unit_vec(),
+            zenith,
+            perez,
+        }
+    }
+
+    fn radiance(&self, dir: Vec3) -> Color {
+        let d = dir.unit_vec();
+        // Below the horizon the sky keeps the color it has at the horizon
+        let cos_theta = d.y().max(0.01);
+        let theta = cos_theta.acos();
+        let gamma = dot(d, self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let luminance = self.zenith[0] * perez_function(&self.perez[0], theta, gamma);
+        let x = self.zenith[1] * perez_function(&self.perez[1], theta, gamma);
+        let y = self.zenith[2] * perez_function(&self.perez[2], theta, gamma);
+        xyy_to_rgb(x, y, luminance)
+    }
+}
+
+fn perez_function(coefficients: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / theta.cos()).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// Converts CIE xyY to linear sRGB.
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 || luminance <= 0.0 {
+        return Color::default();
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Color::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
+
+/// Sun radiance after Rayleigh and aerosol extinction along the optical path,
+/// evaluated at the dominant wavelengths of the red, green and blue primaries.
+fn sun_radiance(sun_direction: Vec3, turbidity: f64) -> Color {
+    if sun_direction.y() <= 0.0 {
+        return Color::default();
+    }
+
+    let theta_s = sun_direction.y().acos();
+    let relative_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    let transmittance = |lambda: f64| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-relative_mass * (rayleigh + aerosol)).exp()
+    };
+
+    SUN_LUMINANCE
+        * Color::new(
+            transmittance(0.680),
+            transmittance(0.550),
+            transmittance(0.440),
+        )
+}
@@ -2,22 +2,101 @@ use std::{fs::File, io::Read};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{camera::Camera, hittable::sphere::Sphere};
+use crate::{
+    adaptive::AdaptiveSampling,
+    animation::Animation,
+    aov::{Aov, AovSettings},
+    background::Background,
+    camera::Camera,
+    crop::Crop,
+    denoise::Denoiser,
+    distributed::Distributed,
+    filter::Filter,
+    hittable::{aabb::Aabb, sphere::Sphere},
+    light::{
+        deserialize_lights,
+        emitters::EmitterBvh,
+        selection::{LightSelection, LightSelector},
+        LightSource,
+    },
+    output::Output,
+    progressive::Progressive,
+    renderer::Integrator,
+    sampler::SamplerKind,
+    tile::{Tile, Tiles},
+    tonemap::ToneMapping,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct State {
     pub samples_per_pixel: i32,
+    /// Replaces the fixed `samples_per_pixel` of the path tracer with a
+    /// number depending on the noise of each pixel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Renders the path traced image in passes, saving it to be resumed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progressive: Option<Progressive>,
     pub max_depth: i32,
     pub aspect_ratio: f64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub width: Option<i32>,
     pub height: u32,
     pub frames: u32,
+    /// Keyframes of the camera and spheres over the frames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<Animation>,
 
     pub camera: Camera,
     pub entities_vec: Vec<Sphere>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub lights: Option<Vec<Sphere>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_lights",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub lights: Option<Vec<LightSource>>,
+    #[serde(default)]
+    pub light_selection: LightSelection,
+    #[serde(skip)]
+    pub light_selector: Option<LightSelector>,
+    /// Quad and disk lights, which paths hit through this hierarchy
+    #[serde(skip)]
+    pub emitters: EmitterBvh,
+
+    #[serde(default)]
+    pub background: Background,
+    #[serde(default)]
+    pub integrator: Integrator,
+    #[serde(default)]
+    pub sampler: SamplerKind,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub tiles: Tiles,
+    /// Renders the tiles on worker processes instead of this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distributed: Option<Distributed>,
+    /// Smooths the noise of the path traced image before it is written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denoise: Option<Denoiser>,
+    /// Varies the random numbers of the render, which is otherwise identical
+    /// from one run to the next
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub output: Output,
+    /// Part of the frame to render, the whole frame when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Crop>,
+    /// Passes rendered alongside the image by the path tracer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aovs: Option<AovSettings>,
+    #[serde(default)]
+    pub tone_mapping: ToneMapping,
+    /// OpenEXR, Radiance HDR or PFM file receiving the linear image, besides
+    /// the output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hdr_output: Option<String>,
 }
 
 impl State {
@@ -30,19 +109,42 @@ impl State {
         frames: u32,
         camera: Camera,
         entities_vec: Vec<Sphere>,
-        lights: Vec<Sphere>
+        lights: Vec<LightSource>
     ) -> Self {
-        Self {
+        let mut state = Self {
             samples_per_pixel,
+            adaptive: None,
+            progressive: None,
             max_depth,
             aspect_ratio,
             width,
             height,
             frames,
+            animation: None,
             camera,
             entities_vec,
-            lights: Some(lights)
-        }
+            lights: Some(lights),
+            light_selection: LightSelection::default(),
+            light_selector: None,
+            emitters: EmitterBvh::default(),
+            background: Background::default(),
+            integrator: Integrator::default(),
+            sampler: SamplerKind::default(),
+            filter: Filter::default(),
+            tiles: Tiles::default(),
+            distributed: None,
+            denoise: None,
+            seed: 0,
+            output: Output::default(),
+            crop: None,
+            aovs: None,
+            tone_mapping: ToneMapping::default(),
+            hdr_output: None,
+        };
+        state.register_emissive_spheres();
+        state.build_light_selector();
+
+        state
     }
 
     pub fn from_json(file_name: &str) -> Self {
@@ -52,11 +154,86 @@ impl State {
         // println!("{}", to_parse);
 
         let mut state: State = serde_json::from_str(&to_parse).unwrap();
+        state.prepare().expect("error loading the background");
 
-        if state.width.is_none() {
-            state.width = Some((state.height as f64 * state.aspect_ratio) as i32);
+        state
+    }
+
+    /// State serialized by `serde_json`, as sent to the workers of a
+    /// distributed render.
+    pub fn from_json_str(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut state: State = serde_json::from_str(json)?;
+        state.prepare()?;
+        Ok(state)
+    }
+
+    /// Fills in what the state file leaves out: the width, the lights of
+    /// emissive spheres and the structures built from the scene.
+    fn prepare(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.width.is_none() {
+            self.width = Some((self.height as f64 * self.aspect_ratio) as i32);
+        }
+        self.register_emissive_spheres();
+        self.build_light_selector();
+        self.background.load()?;
+        if let Some(aovs) = &mut self.aovs {
+            aovs.prepare(&self.entities_vec);
         }
+        Ok(())
+    }
 
-        state
+    /// Adds every sphere with an emissive material to the lights, so that direct
+    /// lighting samples all of them exactly once.
+    fn register_emissive_spheres(&mut self) {
+        let lights = self.lights.get_or_insert_with(Vec::new);
+        for sphere in self.entities_vec.iter() {
+            if sphere.material.emitted().is_none() {
+                continue;
+            }
+            let registered = lights.iter().any(|light| match light {
+                LightSource::Sphere(s) => {
+                    (s.center - sphere.center).near_zero() && s.radius == sphere.radius
+                }
+                _ => false,
+            });
+            if !registered {
+                lights.push(LightSource::Sphere(*sphere));
+            }
+        }
+    }
+
+    /// Prepares the structures direct lighting uses to pick a light and paths
+    /// use to find the emitters they hit, which must be rebuilt whenever the
+    /// lights change.
+    pub fn build_light_selector(&mut self) {
+        let scene = self
+            .entities_vec
+            .iter()
+            .fold(Aabb::default(), |acc, s| acc.union(&s.bounding_box()));
+        let lights = self.lights.as_deref().unwrap_or_default();
+        self.light_selector = LightSelector::new(self.light_selection, lights, &scene);
+        self.emitters = EmitterBvh::new(lights);
+    }
+
+    /// Passes to render: the requested AOVs and the features of the denoiser.
+    pub fn aov_passes(&self) -> Vec<Aov> {
+        let mut passes = self
+            .aovs
+            .as_ref()
+            .map_or(Vec::new(), |aovs| aovs.passes.clone());
+        if self.denoise.is_some() {
+            for aov in Denoiser::FEATURES {
+                if !passes.contains(&aov) {
+                    passes.push(aov);
+                }
+            }
+        }
+        passes
+    }
+
+    /// Pixels of the crop, checked when the arguments are applied.
+    pub fn crop_window(&self) -> Option<Tile> {
+        let frame = (self.width.unwrap() as usize, self.height as usize);
+        self.crop.map(|crop| crop.tile(frame).expect("invalid crop window"))
     }
 }
@@ -5,11 +5,42 @@ use palette::Srgb;
 use crate::{
     camera::Camera,
     hittable::sphere::Sphere,
+    light::LightSource,
     material::{Lambertian, Metal, Light},
+    sampler::{independent::IndependentSampler, SamplerKind},
     state::State,
     vec3::{Point3, Vec3},
 };
 
+/// Red sphere in front of the camera, the scene of the small renders.
+fn small_scene(width: i32, height: u32, samples_per_pixel: i32) -> State {
+    let aspect_ratio = width as f64 / height as f64;
+    let camera = Camera::new(
+        Point3::new(0.0, 0.0, 1.0),
+        Point3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        50.0,
+        aspect_ratio,
+    );
+    let entities_vec = vec![Sphere::new(
+        Point3::new(0.0, 0.0, -1.0),
+        0.5,
+        crate::material::Material::new_lambertian(Srgb::new(0.8, 0.3, 0.3)),
+    )];
+    let (width, max_depth, frames) = (Some(width), 8, 1);
+    State::new(
+        samples_per_pixel,
+        max_depth,
+        aspect_ratio,
+        width,
+        height,
+        frames,
+        camera,
+        entities_vec,
+        vec![],
+    )
+}
+
 #[test]
 pub fn test_serde() {
     use crate::{material::Material, vec3::Point3};
@@ -69,6 +100,17 @@ pub fn camera_serde() {
 
     let camera_deser: Camera = serde_json::from_str(&camera_str).unwrap();
     println!("Camera Deser: {:?}", camera_deser);
+    assert_eq!(serde_json::to_string(&camera_deser).unwrap(), camera_str);
+
+    // Every authoring parameter survives the round trip
+    let json = r#"{"look_from":{"e":[0.0,1.0,1.0]},"look_at":{"e":[0.0,0.0,-1.0]},"vup":{"e":[0.0,1.0,0.0]},"vfov":50.0,"aspect_ratio":2.0,"aperture":0.1,"focus_dist":3.0,"blades":5,"shutter_open":0.0,"shutter_close":0.5,"projection":{"Fisheye":{"fov":180.0}},"stereo":{"layout":"TopBottom","ipd":0.064,"convergence":null}}"#;
+    let camera: Camera = serde_json::from_str(json).unwrap();
+    assert_eq!(serde_json::to_string(&camera).unwrap(), json);
+
+    let duplicate = serde_json::from_str::<Camera>(
+        r#"{"look_from":{"e":[0,1,1]},"vup":{"e":[0,1,0]},"vup":{"e":[0,1,0]}}"#,
+    );
+    assert!(duplicate.unwrap_err().to_string().contains("duplicate field `vup`"));
 }
 
 #[test]
@@ -101,7 +143,11 @@ pub fn state_serde() {
     );
 
     let entities_vec = vec![sphere1, sphere2];
-    let lights = vec![Sphere::new(Point3::new(-3., 2., 1.), 0.3, crate::material::Material::Light(Light::new()))];
+    let lights = vec![LightSource::Sphere(Sphere::new(
+        Point3::new(-3., 2., 1.),
+        0.3,
+        crate::material::Material::Light(Light::new()),
+    ))];
 
     let state = State::new(
         100,
@@ -126,3 +172,1256 @@ fn init_from_file() {
     let state_ser = State::from_json("state.json");
     println!("State Ser: {:?}", state_ser);
 }
+
+#[test]
+fn integrator_serde() {
+    use crate::renderer::Integrator;
+
+    let integrator: Integrator =
+        serde_json::from_str(r#"{"Metropolis":{"mutations_per_pixel":16}}"#).unwrap();
+    match integrator {
+        Integrator::Metropolis(config) => {
+            assert_eq!(config.mutations_per_pixel, 16);
+            assert_eq!(config.chains, 1000);
+        }
+        Integrator::PathTracer => panic!("expected the Metropolis integrator"),
+    }
+
+    let integrator: Integrator = serde_json::from_str(r#""PathTracer""#).unwrap();
+    assert!(matches!(integrator, Integrator::PathTracer));
+
+    // Chains without mutations would leave the image black
+    let mut state = small_scene(4, 2, 1);
+    state.integrator =
+        serde_json::from_str(r#"{"Metropolis":{"mutations_per_pixel":0}}"#).unwrap();
+    let args = crate::cli::Args::parse(std::iter::empty()).unwrap();
+    assert!(args.apply(&mut state).is_err());
+}
+
+#[test]
+fn metropolis_mean() {
+    use crate::{
+        background::Background,
+        mlt::{self, MetropolisConfig},
+        renderer::{add_samples, Accumulation},
+        vec3::{functions::luminance, Color},
+    };
+
+    let mut state = small_scene(16, 8, 64);
+    let spheres = std::mem::take(&mut state.entities_vec);
+    state.background = Background::Solid {
+        color: Srgb::new(0.2, 0.6, 0.4),
+    };
+    let config = MetropolisConfig {
+        mutations_per_pixel: 64,
+        bootstrap_samples: 4000,
+        chains: 16,
+        ..Default::default()
+    };
+
+    // Every path of an empty scene sees the background, whose luminance is
+    // the normalization of the chains
+    let (b, cdf) = mlt::bootstrap(&state, &config);
+    let background = luminance(Color::new(0.2, 0.6, 0.4));
+    assert!((b - background).abs() < 1e-6);
+    assert_eq!(cdf.len(), 4000);
+    assert!((cdf[cdf.len() - 1] - 1.0).abs() < 1e-9);
+
+    // With a sphere in front, both integrators give images of the same mean
+    state.entities_vec = spheres;
+    let mut path_traced = Accumulation::new(&state);
+    add_samples(&state, &mut path_traced, 64, "");
+    let pixels = path_traced.film.resolve();
+    let path_mean = pixels.iter().map(|&c| luminance(c)).sum::<f64>() / pixels.len() as f64;
+    let metropolis = mlt::render(&state, &config);
+    let metropolis_mean = metropolis
+        .chunks(3)
+        .map(|c| luminance(Color::new(c[0], c[1], c[2])))
+        .sum::<f64>()
+        / (metropolis.len() / 3) as f64;
+    assert!(
+        (metropolis_mean - path_mean).abs() < 0.05 * path_mean,
+        "{metropolis_mean} {path_mean}"
+    );
+
+    // Frames a single pixel wide or tall still see the sphere in the middle,
+    // grown to fill the wide pixels of a single row
+    for (width, height, middle) in [(1, 8, 4), (16, 1, 8)] {
+        let mut thin = small_scene(width, height, 1);
+        thin.entities_vec[0].radius = 1.5;
+        thin.background = Background::Solid {
+            color: Srgb::new(0.2, 0.6, 0.4),
+        };
+        let image = mlt::render(&thin, &config);
+        assert!(image.iter().all(|v| v.is_finite()));
+        let pixel = &image[3 * middle..3 * middle + 3];
+        // The background has a third as much red as green, the sphere more
+        assert!(pixel[0] > 0.6 * pixel[1], "{pixel:?}");
+    }
+}
+
+#[test]
+fn distribution_sampling() {
+    use crate::sampling::Distribution1D;
+
+    let distribution = Distribution1D::new(vec![1.0, 3.0]);
+    assert_eq!(distribution.integral(), 2.0);
+
+    let (x, pdf, offset) = distribution.sample_continuous(0.125);
+    assert_eq!(offset, 0);
+    assert!((x - 0.25).abs() < 1e-9);
+    assert!((pdf - 0.5).abs() < 1e-9);
+
+    let (x, pdf, offset) = distribution.sample_continuous(0.625);
+    assert_eq!(offset, 1);
+    assert!((x - 0.75).abs() < 1e-9);
+    assert!((pdf - 1.5).abs() < 1e-9);
+}
+
+#[test]
+fn camera_lens_serde() {
+    let camera: Camera = serde_json::from_str(
+        r#"{"look_from":{"e":[0,0,3]},"look_at":{"e":[0,0,-1]},"vup":{"e":[0,1,0]},
+            "vfov":20,"aspect_ratio":1.5,"aperture":0.1,"blades":6}"#,
+    )
+    .unwrap();
+    assert_eq!(camera.lens().aperture, 0.1);
+    assert_eq!(camera.lens().blades, 6);
+    assert!(camera.lens().focus_dist.is_none());
+
+    let conflicting = serde_json::from_str::<Camera>(
+        r#"{"look_from":{"e":[0,0,3]},"look_at":{"e":[0,0,-1]},"vup":{"e":[0,1,0]},
+            "vfov":20,"aspect_ratio":1.5,"focus_dist":2.0,"autofocus":true}"#,
+    );
+    assert!(conflicting.is_err());
+
+    // Fields are read by name, never by position
+    let sequence = serde_json::from_str::<Camera>(
+        r#"[{"e":[0,0,3]},{"e":[0,0,-1]},{"e":[0,1,0]},20,1.5,0.1]"#,
+    );
+    assert!(sequence.is_err());
+}
+
+#[test]
+fn camera_projections() {
+    use crate::camera::Projection;
+
+    let camera = |projection: &str| -> Camera {
+        serde_json::from_str(&format!(
+            r#"{{"look_from":{{"e":[0,0,0]}},"look_at":{{"e":[0,0,-1]}},"vup":{{"e":[0,1,0]}},
+                "vfov":90,"aspect_ratio":2,"projection":{}}}"#,
+            projection
+        ))
+        .unwrap()
+    };
+
+    let mut sampler = IndependentSampler::new(0);
+    let ortho = camera(r#"{"Orthographic":{"height":2.0}}"#);
+    assert_eq!(ortho.projection(), Projection::Orthographic { height: 2.0 });
+    let ray = ortho.get_ray(1.0, 1.0, &mut sampler).unwrap();
+    // Camera bases come from the approximate unit_vec
+    let close = |a: Vec3, b: Vec3| (a - b).norm_squared() < 1e-4;
+    assert!(close(ray.origin(), Point3::new(2.0, 1.0, 0.0)));
+
+    // The middle of a panorama looks at look_at, its edges behind the camera
+    let equirect = camera(r#""Equirectangular""#);
+    let front = equirect.get_ray(0.5, 0.5, &mut sampler).unwrap().dir();
+    assert!(close(front, Vec3::new(0.0, 0.0, -1.0)));
+    let back = equirect.get_ray(0.0, 0.5, &mut sampler).unwrap().dir();
+    assert!(close(back, Vec3::new(0.0, 0.0, 1.0)));
+
+    let fisheye = camera(r#"{"Fisheye":{"fov":180.0}}"#);
+    assert!(fisheye.get_ray(0.0, 0.5, &mut sampler).is_none());
+    assert!(fisheye.get_ray(0.5, 0.5, &mut sampler).is_some());
+}
+
+#[test]
+fn camera_stereo() {
+    let camera: Camera = serde_json::from_str(
+        r#"{"look_from":{"e":[0,0,0]},"look_at":{"e":[0,0,-2]},"vup":{"e":[0,1,0]},
+            "vfov":90,"aspect_ratio":1,"stereo":{"ipd":0.1,"convergence":2.0}}"#,
+    )
+    .unwrap();
+    let stereo = camera.stereo().unwrap();
+    assert_eq!(stereo.layout, crate::camera::StereoLayout::SideBySide);
+
+    // The centers of both views start from each eye and meet at the convergence distance
+    let mut sampler = IndependentSampler::new(0);
+    let left = camera.get_ray(0.25, 0.5, &mut sampler).unwrap();
+    let right = camera.get_ray(0.75, 0.5, &mut sampler).unwrap();
+    assert!((left.origin().x() + 0.05).abs() < 1e-3);
+    assert!((right.origin().x() - 0.05).abs() < 1e-3);
+    let meet = left.at(2.0 / -left.dir().z());
+    assert!(meet.x().abs() < 1e-3);
+    assert!((right.at(2.0 / -right.dir().z()) - meet).norm_squared() < 1e-6);
+}
+
+#[test]
+fn camera_exposure() {
+    use crate::exposure::{white_balance, Exposure};
+
+    let exposure = Exposure {
+        iso: 100.0,
+        shutter_speed: 1.0,
+        f_stop: 1.0,
+        ..Default::default()
+    };
+    assert_eq!(exposure.ev100(), 0.0);
+    assert!((exposure.scale() - 1.0 / 1.2).abs() < 1e-12);
+
+    // A full frame sensor behind a 90° lens has a 12mm focal length
+    assert!((exposure.aperture(90.0) - 0.012).abs() < 1e-9);
+
+    // A black body at the temperature of D65 sits close to the sRGB white
+    let m = white_balance(6504.0);
+    for (i, row) in m.iter().enumerate() {
+        for (j, x) in row.iter().enumerate() {
+            let expected = if i == j { 1.0 } else { 0.0 };
+            assert!((x - expected).abs() < 0.05, "{:?}", m);
+        }
+    }
+
+    let camera = serde_json::from_str::<Camera>(
+        r#"{"look_from":{"e":[0,0,3]},"look_at":{"e":[0,0,-1]},"vup":{"e":[0,1,0]},
+            "vfov":90,"aspect_ratio":1.5,"exposure":{"f_stop":2.0}}"#,
+    )
+    .unwrap();
+    assert!((camera.lens().aperture - 0.006).abs() < 1e-9);
+}
+
+#[test]
+fn moving_sphere_hit() {
+    use crate::{hittable::Hittable, material::Material, ray::Ray};
+
+    let sphere: Sphere = serde_json::from_str(
+        r#"{"center":{"e":[0,0,-1]},"center_end":{"e":[2,0,-1]},"radius":0.5,
+            "material":{"Lambertian":{"albedo":[0.5,0.5,0.5]}}}"#,
+    )
+    .unwrap();
+    assert!(matches!(sphere.material, Material::Lambertian(_)));
+
+    // The bounds cover the whole motion
+    let bounds = sphere.bounding_box();
+    assert_eq!(bounds.min.x(), -0.5);
+    assert_eq!(bounds.max.x(), 2.5);
+
+    let dir = Vec3::new(0.0, 0.0, -1.0);
+    let origin = Point3::new(2.0, 0.0, 0.0);
+    assert!(sphere.hit(&Ray::with_time(origin, dir, 0.0), 0.001, f64::INFINITY).is_none());
+    let hit = sphere.hit(&Ray::with_time(origin, dir, 1.0), 0.001, f64::INFINITY);
+    assert_eq!(hit.map(|h| h.time), Some(1.0));
+}
+
+#[test]
+fn alias_table_sampling() {
+    use crate::sampling::AliasTable;
+
+    let table = AliasTable::new(&[1.0, 0.0, 3.0]);
+    assert!((table.pmf(0) - 0.25).abs() < 1e-9);
+    assert_eq!(table.pmf(1), 0.0);
+
+    // Every index is reached with its own probability over a uniform sweep
+    let n = 1200;
+    let mut counts = [0; 3];
+    for i in 0..n {
+        let (index, pmf) = table.sample((i as f64 + 0.5) / n as f64);
+        assert_eq!(pmf, table.pmf(index));
+        counts[index] += 1;
+    }
+    assert_eq!(counts, [300, 0, 900]);
+}
+
+#[test]
+fn light_sources() {
+    use crate::{
+        light::{
+            deserialize_lights, DirectionalLight, DiskLight, Illuminate, PointLight, QuadLight,
+            SpotLight,
+        },
+        ray::Ray,
+        vec3::functions::dot,
+    };
+    use serde::Deserialize;
+    use std::f64::consts::PI;
+
+    // Every kind reads from the state, along with the bare emissive spheres
+    // the lights used to be
+    #[derive(Deserialize)]
+    struct Lights {
+        #[serde(deserialize_with = "deserialize_lights")]
+        lights: Option<Vec<LightSource>>,
+    }
+    let json = r#"{"lights": [
+        {"Point": {"position": {"e": [0, 2, 0]}, "color": [1, 1, 1], "power": 10}},
+        {"Spot": {"position": {"e": [0, 2, 0]}, "direction": {"e": [0, -1, 0]},
+            "color": [1, 1, 1], "power": 10, "cone_angle": 30, "falloff_start": 20}},
+        {"Directional": {"direction": {"e": [0, -1, 0]}, "color": [1, 1, 1], "irradiance": 3}},
+        {"Quad": {"corner": {"e": [0, 1, 0]}, "edge_u": {"e": [1, 0, 0]},
+            "edge_v": {"e": [0, 0, 1]}, "color": [1, 1, 1], "power": 5}},
+        {"Disk": {"center": {"e": [0, 1, 0]}, "normal": {"e": [0, -1, 0]}, "radius": 0.5,
+            "color": [1, 1, 1], "power": 5}},
+        {"center": {"e": [0, 3, 0]}, "radius": 0.5, "material": {"Light": {}}}
+    ]}"#;
+    let lights = serde_json::from_str::<Lights>(json).unwrap().lights.unwrap();
+    let kinds: Vec<&str> = lights
+        .iter()
+        .map(|light| match light {
+            LightSource::Point(_) => "point",
+            LightSource::Spot(_) => "spot",
+            LightSource::Directional(_) => "directional",
+            LightSource::Quad(_) => "quad",
+            LightSource::Disk(_) => "disk",
+            LightSource::Sphere(_) => "sphere",
+        })
+        .collect();
+    assert_eq!(kinds, ["point", "spot", "directional", "quad", "disk", "sphere"]);
+
+    let white = Srgb::new(1.0, 1.0, 1.0);
+    let mut sampler = IndependentSampler::new(5);
+    let origin = Point3::default();
+
+    // A point light of 4π W has an intensity of 1 W/sr, falling off with the
+    // square of the distance
+    let point = PointLight {
+        position: Point3::new(0.0, 2.0, 0.0),
+        color: white,
+        power: 4.0 * PI,
+    };
+    let sample = point.sample(origin, &mut sampler).unwrap();
+    assert!((sample.radiance.x() - 0.25).abs() < 1e-12);
+    assert!((sample.distance - 2.0).abs() < 1e-12);
+    // Directions are normalized with an approximate inverse square root
+    assert!((sample.dir.y() - 1.0).abs() < 1e-2);
+
+    // A spot light spreads its power over its cone and gives nothing outside
+    let spot = SpotLight {
+        position: Point3::new(0.0, 1.0, 0.0),
+        direction: Vec3::new(0.0, -1.0, 0.0),
+        color: white,
+        power: 2.0 * PI * (1.0 - 30f64.to_radians().cos()),
+        cone_angle: 30.0,
+        falloff_start: 30.0,
+    };
+    let inside = spot.sample(origin, &mut sampler).unwrap();
+    assert!((inside.radiance.x() - 1.0).abs() < 1e-9);
+    assert!(spot.sample(Point3::new(1.0, 0.0, 0.0), &mut sampler).is_none());
+    let fading = SpotLight {
+        falloff_start: 10.0,
+        ..spot
+    };
+    // 20° off the axis, between the start of the falloff and the edge
+    let p = Point3::new(20f64.to_radians().tan(), 0.0, 0.0);
+    let faded = fading.sample(p, &mut sampler).unwrap().radiance.x();
+    assert!(faded > 0.0 && faded < fading.sample(origin, &mut sampler).unwrap().radiance.x());
+
+    let sun = DirectionalLight {
+        direction: Vec3::new(0.0, -1.0, 0.0),
+        color: white,
+        irradiance: 3.0,
+    };
+    let sample = sun.sample(origin, &mut sampler).unwrap();
+    assert_eq!(sample.radiance.x(), 3.0);
+    assert_eq!(sample.distance, f64::INFINITY);
+
+    // Sampling the area of a quad gives the irradiance found by integrating
+    // over it uniformly
+    let quad = QuadLight {
+        corner: Point3::new(-0.5, 1.0, -1.0),
+        edge_u: Vec3::new(1.0, 0.0, 0.0),
+        edge_v: Vec3::new(0.0, 0.0, 1.5),
+        color: white,
+        power: 5.0,
+    };
+    let normal = Vec3::new(0.0, 1.0, 0.0);
+    let n = 40_000;
+    let sampled: f64 = (0..n)
+        .filter_map(|_| quad.sample(origin, &mut sampler))
+        .map(|s| dot(s.dir, normal) * s.radiance.x())
+        .sum::<f64>()
+        / n as f64;
+    let radiance = 5.0 / (PI * 1.5);
+    let steps = 300;
+    let mut integrated = 0.0;
+    for i in 0..steps {
+        for j in 0..steps {
+            let (a, b) = ((i as f64 + 0.5) / steps as f64, (j as f64 + 0.5) / steps as f64);
+            let to_light = quad.corner + a * quad.edge_u + b * quad.edge_v - origin;
+            let d2 = to_light.norm_squared();
+            // Cosines at the receiver and at the light, facing each other
+            let cos = to_light.y() / d2.sqrt();
+            integrated += radiance * cos * cos / d2 * 1.5 / (steps * steps) as f64;
+        }
+    }
+    assert!((sampled - integrated).abs() < 0.02 * integrated);
+
+    // The irradiance under the center of a disk is known in closed form
+    let disk = DiskLight {
+        center: Point3::new(0.0, 2.0, 0.0),
+        normal: Vec3::new(0.0, -1.0, 0.0),
+        radius: 1.0,
+        color: white,
+        power: 3.0,
+    };
+    let sampled: f64 = (0..n)
+        .filter_map(|_| disk.sample(origin, &mut sampler))
+        .map(|s| dot(s.dir, normal) * s.radiance.x())
+        .sum::<f64>()
+        / n as f64;
+    let radiance = 3.0 / (PI * PI);
+    let exact = radiance * PI / (1.0 + 4.0);
+    assert!((sampled - exact).abs() < 0.02 * exact);
+
+    // Rays see the radiance of the front of area lights only
+    let up = Ray::new(Point3::new(0.1, 0.0, -0.2), Vec3::new(0.0, 1.0, 0.0));
+    let (t, seen) = quad.emitted(&up, f64::INFINITY).unwrap();
+    assert!((t - 1.0).abs() < 1e-12);
+    assert!((seen.x() - 5.0 / (PI * 1.5)).abs() < 1e-2 * seen.x());
+    assert!(quad.emitted(&up, 0.5).is_none());
+    let down = Ray::new(Point3::new(0.1, 2.0, -0.2), Vec3::new(0.0, -1.0, 0.0));
+    assert!(quad.emitted(&down, f64::INFINITY).is_none());
+    assert!(disk.emitted(&up, f64::INFINITY).is_some());
+    let beside = Ray::new(Point3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+    assert!(disk.emitted(&beside, f64::INFINITY).is_none());
+}
+
+#[test]
+fn emitter_hierarchy() {
+    use crate::{
+        light::{emitters::EmitterBvh, DiskLight, Illuminate, PointLight, QuadLight},
+        ray::Ray,
+        sampler::Sampler,
+    };
+
+    // A grid of quads and disks facing down, hit from below
+    let mut lights = vec![LightSource::Point(PointLight {
+        position: Point3::new(0.0, 2.0, 0.0),
+        color: Srgb::new(1.0, 1.0, 1.0),
+        power: 1.0,
+    })];
+    for i in 0..6 {
+        for j in 0..6 {
+            let (x, z) = (i as f64 - 3.0, j as f64 - 3.0);
+            let y = 1.0 + 0.1 * ((i + j) % 3) as f64;
+            lights.push(if (i + j) % 2 == 0 {
+                LightSource::Quad(QuadLight {
+                    corner: Point3::new(x, y, z),
+                    edge_u: Vec3::new(0.0, 0.0, 0.8),
+                    edge_v: Vec3::new(0.8, 0.0, 0.0),
+                    color: Srgb::new(1.0, 1.0, 1.0),
+                    power: (i * 6 + j + 1) as f64,
+                })
+            } else {
+                LightSource::Disk(DiskLight {
+                    center: Point3::new(x + 0.4, y, z + 0.4),
+                    normal: Vec3::new(0.0, -1.0, 0.0),
+                    radius: 0.4,
+                    color: Srgb::new(1.0, 1.0, 1.0),
+                    power: (i * 6 + j + 1) as f64,
+                })
+            });
+        }
+    }
+    let bvh = EmitterBvh::new(&lights);
+
+    // The hierarchy finds the closest emitter a pass over all of them finds
+    let mut sampler = IndependentSampler::new(3);
+    let mut hits = 0;
+    for _ in 0..2000 {
+        let (u1, u2) = sampler.get_2d();
+        let (v1, v2) = sampler.get_2d();
+        let origin = Point3::new(8.0 * u1 - 4.0, -1.0, 8.0 * u2 - 4.0);
+        let ray = Ray::new(origin, Vec3::new(v1 - 0.5, 1.0, v2 - 0.5));
+        let closest = lights
+            .iter()
+            .filter_map(|light| light.emitted(&ray, f64::INFINITY))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        let found = bvh.hit(&lights, &ray, f64::INFINITY);
+        assert_eq!(found.map(|(t, _)| t), closest.map(|(t, _)| t));
+        assert_eq!(found.map(|(_, c)| c.x()), closest.map(|(_, c)| c.x()));
+        hits += usize::from(found.is_some());
+    }
+    assert!(hits > 100);
+    // Nothing is found beyond `t_max`
+    let ray = Ray::new(Point3::new(0.2, 0.0, 0.2), Vec3::new(0.0, 1.0, 0.0));
+    assert!(bvh.hit(&lights, &ray, 0.5).is_none());
+}
+
+#[test]
+fn dark_light_spheres() {
+    use crate::{
+        hittable::aabb::Aabb,
+        light::{
+            selection::{LightSelection, LightSelector},
+            PointLight,
+        },
+        material::Material,
+    };
+
+    // A sphere listed as a light without emitting is never picked, rather
+    // than being taken for a light at infinity
+    let lights = [
+        LightSource::Sphere(Sphere::new(
+            Point3::new(0.0, 3.0, 0.0),
+            0.5,
+            Material::new_lambertian(Srgb::new(0.5, 0.5, 0.5)),
+        )),
+        LightSource::Point(PointLight {
+            position: Point3::new(0.0, 2.0, 0.0),
+            color: Srgb::new(1.0, 1.0, 1.0),
+            power: 10.0,
+        }),
+    ];
+    let scene = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+    for selection in [LightSelection::Power, LightSelection::Bvh] {
+        let selector = LightSelector::new(selection, &lights, &scene).unwrap();
+        for i in 0..16 {
+            let u = (i as f64 + 0.5) / 16.0;
+            let picked = selector.sample(Point3::default(), Vec3::new(0.0, 1.0, 0.0), u);
+            assert_eq!(picked, Some((1, 1.0)));
+        }
+    }
+}
+
+#[test]
+fn sky_serde() {
+    use crate::background::Background;
+
+    let background: Background =
+        serde_json::from_str(r#"{"Sky":{"elevation":45.0}}"#).unwrap();
+    let Background::Sky(sky) = background else {
+        panic!("expected a sky background");
+    };
+    assert_eq!(sky.azimuth, 0.0);
+    assert_eq!(sky.turbidity, 3.0);
+
+    // Azimuth 0 puts the sun towards -z
+    let sun = sky.sun_direction();
+    assert!(sun.x().abs() < 1e-9);
+    assert!(sun.z() < 0.0);
+    assert!((sun.y() - 0.5f64.sqrt()).abs() < 1e-9);
+}
+
+#[test]
+fn sampler_stratification() {
+    // Every sampler but the independent one puts a single sample of the pixel
+    // in each sixteenth of the first dimension, and most in each cell of a
+    // 4x4 grid
+    let n = 16;
+    for kind in [SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+        let mut sampler = kind.create(n, 0);
+        let mut strata_1d = vec![0; n as usize];
+        let mut strata_2d = vec![0; n as usize];
+        for i in 0..n {
+            sampler.start_pixel_sample((3, 7), i);
+            strata_1d[(sampler.get_1d() * n as f64) as usize] += 1;
+            sampler.start_pixel_sample((3, 7), i);
+            let (x, y) = sampler.get_2d();
+            strata_2d[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+        }
+        assert!(strata_1d.iter().all(|&c| c == 1), "{:?}", kind);
+        if kind != SamplerKind::Halton {
+            assert!(strata_2d.iter().all(|&c| c == 1), "{:?}", kind);
+        }
+    }
+
+    // Samples only depend on the pixel, the sample and the dimension
+    let mut a = SamplerKind::BlueNoise.create(n, 0);
+    let mut b = SamplerKind::BlueNoise.create(n, 0);
+    a.start_pixel_sample((1, 2), 5);
+    b.start_pixel_sample((1, 2), 5);
+    assert_eq!(a.get_2d(), b.get_2d());
+    assert!((0.0..1.0).contains(&a.get_1d()));
+}
+
+#[test]
+fn seeded_render() {
+    use crate::{aov::AovFilm, film::Film, material::Material, renderer::render_line};
+
+    let mut state = small_scene(32, 16, 4);
+    state.entities_vec.push(Sphere::new(
+        Point3::new(0.0, -100.5, -1.0),
+        100.0,
+        Material::new_metal(Srgb::new(0.5, 0.5, 0.5), 0.4),
+    ));
+
+    let line = |state: &State| {
+        let mut film = Film::for_row(32, 16, 8, &state.filter);
+        let stats = render_line(&mut film, &mut AovFilm::default(), state, 8);
+        assert!(stats.iter().all(|s| s.count() == state.samples_per_pixel as u32));
+        let pixels: Vec<[f64; 3]> = film.resolve().iter().map(|c| [c.x(), c.y(), c.z()]).collect();
+        pixels
+    };
+
+    // The same seed gives the same image, another one different noise
+    let first = line(&state);
+    assert_eq!(line(&state), first);
+    state.seed = 1;
+    assert_ne!(line(&state), first);
+}
+
+#[test]
+fn adaptive_sampling() {
+    use crate::adaptive::{AdaptiveSampling, PixelStats};
+
+    let mut stats = PixelStats::default();
+    for value in [1.0, 2.0, 3.0, 4.0] {
+        stats.add(value);
+    }
+    assert!((stats.mean() - 2.5).abs() < 1e-12);
+    assert!((stats.variance() - 5.0 / 3.0).abs() < 1e-12);
+
+    // Constant pixels stop after the first batch, noisy ones keep going
+    let adaptive = AdaptiveSampling {
+        min_samples: 4,
+        ..Default::default()
+    };
+    let mut flat = PixelStats::default();
+    (0..4).for_each(|_| flat.add(0.5));
+    assert!(adaptive.converged(&flat));
+    assert!(!adaptive.converged(&stats));
+
+    let adaptive: AdaptiveSampling = serde_json::from_str(r#"{"max_samples":64}"#).unwrap();
+    assert_eq!(adaptive.min_samples, 16);
+}
+
+#[test]
+fn film_filters() {
+    use crate::{film::Film, filter::Filter, vec3::Color};
+
+    // The default box filter keeps every sample in the pixel it falls in
+    let filter = Filter::default();
+    let mut film = Film::new(4, 4);
+    film.splat((1.0, 2.0), Color::new(1.0, 1.0, 1.0), &filter);
+    film.splat((1.99, 2.5), Color::new(3.0, 3.0, 3.0), &filter);
+    let pixels = film.resolve();
+    assert!((pixels[2 * 4 + 1].x() - 2.0).abs() < 1e-12);
+    assert_eq!(pixels.iter().filter(|p| p.x() > 0.0).count(), 1);
+
+    // Wider filters spread a sample over its neighbours, and bands add up
+    let filter: Filter = serde_json::from_str(r#"{"Gaussian":{"radius":1.5}}"#).unwrap();
+    assert_eq!(filter, Filter::Gaussian { radius: 1.5, sigma: 0.5 });
+    let mut band = Film::for_row(4, 4, 0, &filter);
+    assert_eq!(band.rows(), 0..3);
+    band.splat((1.5, 0.5), Color::new(1.0, 0.0, 0.0), &filter);
+    let mut film = Film::new(4, 4);
+    film.merge(&band);
+    let pixels = film.resolve();
+    assert!(pixels[1].x() > 0.99 && pixels[4 + 2].x() > 0.99);
+    assert_eq!(pixels[3 * 4 + 1].x(), 0.0);
+
+    for filter in [
+        Filter::Tent { radius: 1.0 },
+        Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 },
+        Filter::Lanczos { radius: 3.0, tau: 3.0 },
+    ] {
+        assert!(filter.evaluate(0.0, 0.0) > 0.0);
+        assert_eq!(filter.evaluate(filter.radius(), 0.0), 0.0);
+    }
+}
+
+#[test]
+fn hdr_output() {
+    use crate::{background::read_hdr_image, output::write_hdr_image, vec3::Color};
+
+    let pixels: Vec<Color> = (0..6)
+        .map(|i| {
+            let v = 0.25 + 10f64.powi(i - 2);
+            Color::new(v, 0.5 * v, 2.0 * v)
+        })
+        .collect();
+    let dir = std::env::temp_dir();
+
+    // EXR keeps full float values, Radiance 8 bit mantissas sharing an exponent
+    for (name, tolerance) in [("hdr_output.exr", 1e-3), ("hdr_output.hdr", 1e-2)] {
+        let path = dir.join(name);
+        let path = path.to_str().unwrap();
+        write_hdr_image(path, &pixels, (3, 2)).unwrap();
+        let (width, height, read) = read_hdr_image(path).unwrap();
+        assert_eq!((width, height), (3, 2));
+        for (a, b) in pixels.iter().zip(&read) {
+            assert!((a.x() - b.x()).abs() <= tolerance * a.z());
+            assert!((a.z() - b.z()).abs() <= tolerance * a.z());
+        }
+    }
+
+    let path = dir.join("hdr_output.pfm");
+    write_hdr_image(path.to_str().unwrap(), &pixels, (3, 2)).unwrap();
+    let mut data = Vec::new();
+    File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+    assert!(data.starts_with(b"PF\n3 2\n-1.0\n"));
+    // Rows are stored bottom up, so the file starts with the second row
+    let first = f32::from_le_bytes(data[12..16].try_into().unwrap());
+    assert_eq!(first, pixels[3].x() as f32);
+
+    assert!(write_hdr_image("render.tiff", &pixels, (3, 2)).is_err());
+}
+
+#[test]
+fn tone_mapping() {
+    use crate::{
+        tonemap::{srgb_oetf, ToneMapper, ToneMapping},
+        vec3::Color,
+    };
+
+    // The sRGB curve is linear near black and maps mid grey to about 0.46
+    assert!((srgb_oetf(0.001) - 0.01292).abs() < 1e-9);
+    assert!((srgb_oetf(0.18) - 0.4614).abs() < 1e-3);
+    assert_eq!(ToneMapping::default().encode(Color::new(0.5, 2.0, -1.0)), [188, 255, 0]);
+
+    let grey = |operator, v: f64| {
+        let tone_mapping = ToneMapping { operator, exposure: 0.0 };
+        tone_mapping.apply(Color::new(v, v, v)).y()
+    };
+    assert!((grey(ToneMapper::Reinhard, 1.0) - 0.5).abs() < 1e-9);
+    assert!((grey(ToneMapper::ExtendedReinhard { white: 4.0 }, 4.0) - 1.0).abs() < 1e-9);
+    for operator in [ToneMapper::Aces, ToneMapper::AgX] {
+        // Filmic curves keep mid grey in the middle, grow steadily and reach white
+        assert!(grey(operator, 0.0) < 0.01);
+        assert!((0.1..0.3).contains(&grey(operator, 0.18)));
+        assert!(grey(operator, 0.5) < grey(operator, 1.0));
+        assert!(grey(operator, 1000.0) > 0.95);
+    }
+
+    let tone_mapping: ToneMapping =
+        serde_json::from_str(r#"{"operator":{"ExtendedReinhard":{}},"exposure":1.0}"#).unwrap();
+    assert_eq!(tone_mapping.operator, ToneMapper::ExtendedReinhard { white: 4.0 });
+    let brighter = tone_mapping.apply(Color::new(0.5, 0.5, 0.5)).x();
+    assert!((brighter - grey(tone_mapping.operator, 1.0)).abs() < 1e-9);
+}
+
+#[test]
+fn output_options() {
+    use crate::{
+        cli::Args,
+        output::{Output, OutputFormat},
+    };
+
+    let args = |line: &str| Args::parse(line.split_whitespace().map(String::from));
+    let camera = Camera::new(
+        Point3::new(0.0, 0.0, 1.0),
+        Point3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        50.0,
+        2.0,
+    );
+    let mut state = State::new(1, 1, 2.0, Some(4), 2, 1, camera, vec![], vec![]);
+    assert_eq!(state.output.format(), Ok(OutputFormat::Png));
+
+    args("-o renders/frame.JPG -q 75").unwrap().apply(&mut state).unwrap();
+    assert_eq!(state.output.path, "renders/frame.JPG");
+    assert_eq!(state.output.format(), Ok(OutputFormat::Jpeg { quality: 75 }));
+    args("--format png16").unwrap().apply(&mut state).unwrap();
+    assert_eq!(state.output.format(), Ok(OutputFormat::Png16));
+
+    assert!(args("-o").is_err());
+    assert!(args("--format gif").is_err());
+    assert!(args("-q 0").is_err());
+    assert!(args("-o render.png -q 80").unwrap().apply(&mut state).is_err());
+    assert!(args("-o render").unwrap().apply(&mut state).is_err());
+
+    let output: Output = serde_json::from_str(r#"{"format":{"Jpeg":{}}}"#).unwrap();
+    assert_eq!(output.path, "data/render.png");
+    assert_eq!(output.format(), Ok(OutputFormat::Jpeg { quality: 90 }));
+
+    // Missing directories are created
+    let dir = std::env::temp_dir().join("output_options");
+    let _ = std::fs::remove_dir_all(&dir);
+    let output = Output {
+        path: dir.join("nested/render.bmp").to_str().unwrap().to_string(),
+        format: None,
+    };
+    let pixels = vec![crate::vec3::Color::new(1.0, 0.5, 0.0); 6];
+    output.write(&pixels, (3, 2), &Default::default()).unwrap();
+    let mut data = Vec::new();
+    File::open(&output.path).unwrap().read_to_end(&mut data).unwrap();
+    // 54 bytes of headers and 2 rows of 9 bytes padded to 12
+    assert_eq!(data.len(), 54 + 2 * 12);
+    assert_eq!(&data[54..57], &[0, 188, 255]);
+
+    // TGA stores sizes on 16 bits, so larger frames are refused before
+    // anything is read or written
+    let output = Output {
+        path: dir.join("wide.tga").to_str().unwrap().to_string(),
+        format: None,
+    };
+    let bounds = (70000, 1);
+    let error = output.write(&[], bounds, &Default::default()).unwrap_err();
+    assert!(error.to_string().contains("65535"));
+    assert!(!std::path::Path::new(&output.path).exists());
+    let crop = crate::tile::Tile {
+        x0: 0,
+        y0: 0,
+        width: 1,
+        height: 1,
+    };
+    let error = output.composite(&pixels[..1], &crop, bounds, &Default::default());
+    assert!(error.unwrap_err().to_string().contains("65535"));
+}
+
+#[test]
+fn aov_passes() {
+    use crate::{
+        aov::{Aov, AovFilm, AovSettings},
+        material::Material,
+        output::Output,
+        renderer::{render_line, trace_path},
+    };
+
+    let mut state = small_scene(32, 16, 4);
+    state.entities_vec.extend([
+        Sphere::new(
+            Point3::new(0.0, -100.5, -1.0),
+            100.0,
+            Material::new_metal(Srgb::new(0.5, 0.5, 0.5), 0.4),
+        ),
+        Sphere::new(
+            Point3::new(1.0, 0.0, -1.0),
+            0.5,
+            Material::new_lambertian(Srgb::new(0.8, 0.3, 0.3)),
+        ),
+    ]);
+    state.lights = Some(vec![LightSource::Sphere(Sphere::new(
+        Point3::new(-1.0, 1.0, -1.0),
+        0.3,
+        Material::Light(Light::new()),
+    ))]);
+    state.build_light_selector();
+    let mut settings: AovSettings =
+        serde_json::from_str(r#"{"passes":["Depth","ObjectId","MaterialId","Emission"]}"#)
+            .unwrap();
+    settings.prepare(&state.entities_vec);
+    state.aovs = Some(settings);
+
+    // The light passes split the radiance of every path
+    let mut sampler = SamplerKind::Independent.create(1, 0);
+    for i in 0..64 {
+        sampler.start_pixel_sample((0, 0), i);
+        let dir = Vec3::new(0.3 * (i % 8) as f64 - 1.0, 0.3 * (i / 8) as f64 - 1.0, -1.0);
+        let ray = crate::ray::Ray::new(Point3::new(0.0, 0.0, 1.0), dir);
+        let (color, aov) = trace_path(ray, &state, sampler.as_mut());
+        let sum = aov.emission
+            + aov.direct_diffuse
+            + aov.indirect_diffuse
+            + aov.direct_specular
+            + aov.indirect_specular;
+        assert!((sum - color).norm_squared() < 1e-18);
+    }
+
+    let passes = state.aov_passes();
+    let mut aovs = AovFilm::new(&passes, 32, 16);
+    let mut band = AovFilm::for_row(&passes, 32, 16, 8, &state.filter);
+    render_line(&mut crate::film::Film::for_row(32, 16, 8, &state.filter), &mut band, &state, 8);
+    aovs.merge(&band);
+    let images = aovs.resolve();
+    let center = 8 * 32 + 16;
+    // Depth and identifiers are never averaged across the edges of spheres
+    assert!((images[0].1[center].x() - 1.5).abs() < 0.05);
+    assert_eq!(images[1].1[center].x(), 1.0);
+    assert!(images[1].1[8 * 32..9 * 32].iter().all(|c| [0.0, 1.0, 2.0, 3.0].contains(&c.x())));
+    // The third sphere shares the material of the first
+    assert_eq!(state.aovs.as_ref().unwrap().material_ids(), &[1, 2, 1]);
+    assert_eq!(images[3].0, Aov::Emission);
+
+    let dir = std::env::temp_dir().join("aov_passes");
+    let output = Output {
+        path: dir.join("render.exr").to_str().unwrap().to_string(),
+        format: None,
+    };
+    let beauty = vec![crate::vec3::Color::default(); 32 * 16];
+    let settings = state.aovs.as_ref().unwrap();
+    settings.write(&output, &beauty, &images, (32, 16)).unwrap();
+    let image = exr::prelude::read_all_flat_layers_from_file(&output.path).unwrap();
+    let names: Vec<String> = image.layer_data[0]
+        .channel_data
+        .list
+        .iter()
+        .map(|c| c.name.to_string())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "B",
+            "G",
+            "R",
+            "depth.Z",
+            "emission.B",
+            "emission.G",
+            "emission.R",
+            "material_id.id",
+            "object_id.id"
+        ]
+    );
+}
+
+#[test]
+fn denoiser() {
+    use crate::{
+        denoise::{Denoiser, Features},
+        sampler::{hash, to_unit},
+        vec3::Color,
+    };
+
+    // Two flat surfaces side by side, one bright and one dark, with noise
+    let (width, height) = (32, 16);
+    let bright = |i: usize| i % width < width / 2;
+    let image: Vec<Color> = (0..width * height)
+        .map(|i| {
+            let noise = to_unit(hash(&[i as u64])) - 0.5;
+            let v = if bright(i) { 1.0 + noise } else { 0.1 + 0.1 * noise };
+            Color::new(v, v, v)
+        })
+        .collect();
+    let albedo: Vec<Color> = (0..width * height)
+        .map(|i| if bright(i) { Color::new(0.8, 0.8, 0.8) } else { Color::new(0.1, 0.1, 0.1) })
+        .collect();
+    let normal = vec![Color::new(0.0, 0.0, 1.0); width * height];
+    let depth = vec![Color::new(2.0, 2.0, 2.0); width * height];
+    let variance: Vec<f64> = (0..width * height)
+        .map(|i| if bright(i) { 1.0 / 12.0 } else { 0.01 / 12.0 })
+        .collect();
+    let features = Features {
+        albedo: &albedo,
+        normal: &normal,
+        depth: &depth,
+        variance: &variance,
+    };
+
+    let denoised = Denoiser::default().denoise(&image, &features, width);
+    let error = |pixels: &[Color], side: bool, expected: f64| {
+        let errors: Vec<f64> = (0..pixels.len())
+            .filter(|&i| bright(i) == side)
+            .map(|i| (pixels[i].x() - expected).powi(2))
+            .collect();
+        errors.iter().sum::<f64>() / errors.len() as f64
+    };
+    // The noise is much lower, and the dark side did not bleed into the bright one
+    assert!(error(&denoised, true, 1.0) < 0.1 * error(&image, true, 1.0));
+    assert!(error(&denoised, false, 0.1) < 0.1 * error(&image, false, 0.1));
+    assert!((0..height).all(|y| denoised[y * width + width / 2 - 1].x() > 0.8));
+
+    // Passes beyond the size of the image change nothing
+    let denoiser = Denoiser {
+        iterations: 100,
+        ..Default::default()
+    };
+    let again = denoiser.denoise(&image, &features, width);
+    assert!(again.iter().zip(&denoised).all(|(a, b)| (*a - *b).near_zero()));
+}
+
+#[test]
+fn progressive_resume() {
+    use crate::{
+        cli::Args,
+        progressive::{self, read_checkpoint, Progressive},
+        renderer::{add_samples, Accumulation},
+    };
+
+    let mut state = small_scene(16, 8, 8);
+    let mut once = Accumulation::new(&state);
+    add_samples(&state, &mut once, 8, "");
+
+    // Stopping halfway and resuming gives the image rendered at once
+    let dir = std::env::temp_dir().join(format!("progressive_resume_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let checkpoint = dir.join("render.bin").to_str().unwrap().to_string();
+    let mut settings = Progressive {
+        pass_samples: 3,
+        target_samples: Some(4),
+        checkpoint: Some(checkpoint.clone()),
+        ..Default::default()
+    };
+    let half = progressive::render(&state, &settings);
+    assert!(half.stats.iter().all(|s| s.count() == 4));
+    settings.target_samples = None;
+    settings.resume = true;
+    let resumed = progressive::render(&state, &settings);
+    assert!(resumed.stats.iter().all(|s| s.count() == 8));
+    for (a, b) in resumed.film.raw().iter().zip(once.film.raw()) {
+        assert!((a - b).abs() < 1e-9);
+    }
+
+    // Checkpoints only resume renders of the same scene with the same
+    // settings, wherever the image goes
+    state.output.path = dir.join("other.png").to_str().unwrap().to_string();
+    assert!(read_checkpoint(&checkpoint, &state).is_ok());
+    state.max_depth += 1;
+    let error = read_checkpoint(&checkpoint, &state).err().unwrap();
+    assert!(error.to_string().contains("scene"));
+    state.max_depth -= 1;
+    state.seed = 1;
+    assert!(read_checkpoint(&checkpoint, &state).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let args = |line: &str| Args::parse(line.split_whitespace().map(String::from));
+    args("--resume --samples 64").unwrap().apply(&mut state).unwrap();
+    let progressive = state.progressive.unwrap();
+    assert!(progressive.resume);
+    assert_eq!(progressive.target_samples, Some(64));
+    assert!(args("--time-limit soon").is_err());
+}
+
+#[test]
+fn tile_orders() {
+    use crate::{
+        film::Film,
+        filter::Filter,
+        tile::{TileOrder, Tiles},
+    };
+
+    for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+        // Every pixel belongs to exactly one tile, edge tiles being smaller
+        let tiles = Tiles { size: 8, order }.split(37, 21);
+        let mut covered = vec![0; 37 * 21];
+        for tile in &tiles {
+            tile.pixels().for_each(|(x, y)| covered[y * 37 + x] += 1);
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+        assert_eq!(tiles.len(), 5 * 3);
+    }
+
+    // The spiral starts in the middle, the Hilbert curve only takes steps
+    // to a neighbouring tile
+    let spiral = Tiles {
+        size: 10,
+        order: TileOrder::Spiral,
+    }
+    .split(50, 50);
+    assert_eq!((spiral[0].x0, spiral[0].y0), (20, 20));
+    let hilbert = Tiles {
+        size: 4,
+        order: TileOrder::Hilbert,
+    }
+    .split(32, 32);
+    for pair in hilbert.windows(2) {
+        let step = pair[0].x0.abs_diff(pair[1].x0) + pair[0].y0.abs_diff(pair[1].y0);
+        assert_eq!(step, 4);
+    }
+
+    // Windows of tiles add up like whole rows
+    let filter = Filter::Gaussian {
+        radius: 1.5,
+        sigma: 0.5,
+    };
+    let mut film = Film::new(8, 8);
+    let mut rows = Film::new(8, 8);
+    let tiles = Tiles {
+        size: 3,
+        order: TileOrder::Hilbert,
+    };
+    for tile in tiles.split(8, 8) {
+        let mut window = Film::for_tile((8, 8), &tile, &filter);
+        for (x, y) in tile.pixels() {
+            let color = crate::vec3::Color::new(x as f64, y as f64, 1.0);
+            window.splat((x as f64 + 0.3, y as f64 + 0.6), color, &filter);
+            let mut row_y = Film::for_row(8, 8, y, &filter);
+            row_y.splat((x as f64 + 0.3, y as f64 + 0.6), color, &filter);
+            rows.merge(&row_y);
+        }
+        film.merge(&window);
+    }
+    for (a, b) in film.raw().iter().zip(rows.raw()) {
+        assert!((a - b).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn crop_composite() {
+    use crate::{
+        crop::{Crop, CropWindow},
+        output::{Output, OutputFormat},
+        tile::Tile,
+        tonemap::ToneMapping,
+        vec3::Color,
+    };
+
+    let crop = |window: &str| Crop {
+        window: CropWindow::parse(window).unwrap(),
+        composite: false,
+    };
+    let expected = Tile {
+        x0: 2,
+        y0: 1,
+        width: 4,
+        height: 2,
+    };
+    assert_eq!(crop("2,1,6,3").tile((8, 4)), Ok(expected));
+    // Normalized windows hold the pixels whose center is inside
+    assert_eq!(crop("0.25, 0.2, 0.75, 0.75").tile((8, 4)), Ok(expected));
+    assert!(crop("2,1,9,3").tile((8, 4)).is_err());
+    assert!(crop("2,1,2,3").tile((8, 4)).is_err());
+    assert!(CropWindow::parse("0,0,1").is_err());
+
+    // Pasting a crop into a float image only replaces its pixels
+    let dir = std::env::temp_dir().join("crop_composite");
+    let _ = std::fs::remove_dir_all(&dir);
+    let output = Output {
+        path: dir.join("render.pfm").to_str().unwrap().to_string(),
+        format: None,
+    };
+    let frame: Vec<Color> = (0..32).map(|i| Color::new(i as f64, 0.5, 1.0)).collect();
+    let tone_mapping = ToneMapping::default();
+    output.write(&frame, (8, 4), &tone_mapping).unwrap();
+    let pixels = vec![Color::new(-1.0, 2.0, 3.0); expected.len()];
+    output
+        .composite(&pixels, &expected, (8, 4), &tone_mapping)
+        .unwrap();
+    let (width, height, composited) =
+        crate::output::read_float_image(&output.path, OutputFormat::Pfm).unwrap();
+    assert_eq!((width, height), (8, 4));
+    for (i, c) in composited.iter().enumerate() {
+        let inside = expected.pixels().any(|(x, y)| y * 8 + x == i);
+        let reference = if inside {
+            Color::new(-1.0, 2.0, 3.0)
+        } else {
+            frame[i]
+        };
+        assert_eq!(
+            [c.x(), c.y(), c.z()],
+            [reference.x(), reference.y(), reference.z()]
+        );
+    }
+    let indices: Vec<usize> = (0..32).collect();
+    assert_eq!(expected.extract(&indices, 8), [10, 11, 12, 13, 18, 19, 20, 21]);
+
+    // The existing image must have the size of the frame
+    assert!(output.composite(&pixels, &expected, (8, 5), &tone_mapping).is_err());
+}
+
+#[test]
+fn distributed_render() {
+    use crate::{
+        distributed::{self, Distributed},
+        renderer::{add_samples, Accumulation},
+    };
+    use std::{io::Write, net::TcpListener, time::Duration};
+
+    let state = small_scene(24, 12, 4);
+    let mut local = Accumulation::new(&state);
+    add_samples(&state, &mut local, 4, "");
+
+    // Two workers share the tiles, those of a worker that cannot be reached
+    // or stalls on its tile going to the others
+    let mut workers = Vec::new();
+    for _ in 0..2 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        workers.push(listener.local_addr().unwrap().to_string());
+        std::thread::spawn(move || distributed::listen(listener));
+    }
+    let unreachable = TcpListener::bind("127.0.0.1:0").unwrap();
+    workers.push(unreachable.local_addr().unwrap().to_string());
+    drop(unreachable);
+    let stalled = TcpListener::bind("127.0.0.1:0").unwrap();
+    workers.push(stalled.local_addr().unwrap().to_string());
+    std::thread::spawn(move || {
+        let (mut stream, _) = stalled.accept().unwrap();
+        stream.write_all(b"READY 0\n").unwrap();
+        std::thread::sleep(Duration::from_secs(10));
+    });
+    let settings = Distributed {
+        workers,
+        job_size: 5,
+        timeout: 1,
+    };
+    let remote = distributed::render(&state, &settings);
+    for (a, b) in remote.film.raw().iter().zip(local.film.raw()) {
+        assert!((a - b).abs() < 1e-9);
+    }
+    assert_eq!(remote.total_samples(), local.total_samples());
+}
+
+#[test]
+fn animation_keyframes() {
+    use crate::{
+        animation::{Animation, Interpolation, KeyValue, Keyframe, Target, Track},
+        cli::Args,
+        light::LightSource,
+        material::Material,
+        output::numbered_path,
+    };
+
+    let key = |frame: f64, value: f64, interpolation| Keyframe {
+        frame,
+        value: KeyValue::Number(value),
+        interpolation,
+    };
+    let linear = Track {
+        target: Target::Vfov,
+        keys: vec![
+            key(1.0, 40.0, Interpolation::Linear),
+            key(3.0, 20.0, Interpolation::Linear),
+        ],
+    };
+    assert_eq!(linear.value_at(2.0)[0], 30.0);
+    // Values are held outside the keys
+    assert_eq!(linear.value_at(0.0)[0], 40.0);
+    assert_eq!(linear.value_at(7.0)[0], 20.0);
+
+    // Bézier curves ease out of the first key and pass smoothly through the
+    // middle one
+    let bezier = Track {
+        target: Target::Vfov,
+        keys: vec![
+            key(1.0, 0.0, Interpolation::Bezier),
+            key(3.0, 2.0, Interpolation::Bezier),
+            key(5.0, 4.0, Interpolation::Bezier),
+        ],
+    };
+    assert!(bezier.value_at(1.5)[0] < 0.5);
+    assert!((bezier.value_at(3.0)[0] - 2.0).abs() < 1e-12);
+    let slope = |f: f64| (bezier.value_at(f + 1e-6)[0] - bezier.value_at(f - 1e-6)[0]) / 2e-6;
+    assert!((slope(3.0) - 1.0).abs() < 1e-6);
+
+    let mut state = small_scene(16, 8, 4);
+    state.frames = 3;
+    let light = Sphere::new(
+        Point3::new(0.0, 2.0, -1.0),
+        0.2,
+        Material::Light(crate::material::Light::new()),
+    );
+    state.entities_vec.push(light);
+    state.lights = Some(vec![LightSource::Sphere(light)]);
+    state.build_light_selector();
+    let triple = |frame: f64, value: [f64; 3]| Keyframe {
+        frame,
+        value: KeyValue::Triple(value),
+        interpolation: Interpolation::Linear,
+    };
+    let animation = Animation {
+        tracks: vec![
+            linear.clone(),
+            Track {
+                target: Target::Center(1),
+                keys: vec![triple(1.0, [0.0, 2.0, -1.0]), triple(3.0, [2.0, 2.0, -1.0])],
+            },
+        ],
+    };
+    animation.check(&state).unwrap();
+    animation.apply(&mut state, 2);
+    assert_eq!(state.camera.view().vfov, 30.0);
+    assert_eq!(state.entities_vec[1].center.x(), 1.0);
+    // The light registered for the emissive sphere moves with it
+    let lights: Vec<f64> = state
+        .lights
+        .iter()
+        .flatten()
+        .filter_map(|light| match light {
+            LightSource::Sphere(s) => Some(s.center.x()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lights, [1.0]);
+
+    let mut invalid = animation.clone();
+    invalid.tracks[1].target = Target::Fuzz(1);
+    assert!(invalid.check(&state).is_err());
+    invalid.tracks[1].target = Target::Center(2);
+    assert!(invalid.check(&state).is_err());
+
+    assert_eq!(numbered_path("data/render.png", 12), "data/render_0012.png");
+    let args = |line: &str| Args::parse(line.split_whitespace().map(String::from));
+    let range = args("--frames 2-3").unwrap();
+    assert_eq!(range.frame_range(&state), 2..=3);
+    assert_eq!(args("").unwrap().frame_range(&state), 1..=3);
+    assert!(args("--frames 3-2").is_err());
+    assert!(args("--frames 2-4").unwrap().apply(&mut state).is_err());
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+/// Square tiles the path tracer splits the image into, rendered in parallel.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tiles {
+    /// Side of the tiles in pixels, smaller on the right and bottom edges
+    pub size: usize,
+    pub order: TileOrder,
+}
+
+impl Default for Tiles {
+    fn default() -> Self {
+        Self {
+            size: 32,
+            order: TileOrder::default(),
+        }
+    }
+}
+
+/// Order in which the tiles are handed to the threads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TileOrder {
+    /// Row by row from the top left corner.
+    #[default]
+    Scanline,
+    /// Ring by ring from the center of the image, which usually holds the
+    /// subject.
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles next to each other.
+    Hilbert,
+}
+
+/// Rectangle of pixels of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    /// Whole image of `width` by `height` pixels.
+    pub fn frame(width: usize, height: usize) -> Self {
+        Self {
+            x0: 0,
+            y0: 0,
+            width,
+            height,
+        }
+    }
+
+    /// Row `y` of an image `width` pixels wide.
+    pub fn row(width: usize, y: usize) -> Self {
+        Self {
+            x0: 0,
+            y0: y,
+            width,
+            height: 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Pixels of the tile, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (self.y0..self.y0 + self.height)
+            .flat_map(move |y| (self.x0..self.x0 + self.width).map(move |x| (x, y)))
+    }
+
+    /// Values of the tile's pixels in `image`, `width` pixels wide.
+    pub fn extract<T: Copy>(&self, image: &[T], width: usize) -> Vec<T> {
+        self.pixels().map(|(x, y)| image[y * width + x]).collect()
+    }
+
+    /// Copies `values`, the tile's pixels row by row with `channels` values
+    /// each, into `image`, `width` pixels wide.
+    pub fn paste<T: Copy>(&self, image: &mut [T], values: &[T], width: usize, channels: usize) {
+        for (row, values) in values.chunks_exact(self.width * channels).enumerate() {
+            let start = ((self.y0 + row) * width + self.x0) * channels;
+            image[start..start + values.len()].copy_from_slice(values);
+        }
+    }
+}
+
+impl Tiles {
+    /// Tiles covering an image of `width` by `height` pixels, in their order.
+    pub fn split(&self, width: usize, height: usize) -> Vec<Tile> {
+        self.split_region(&Tile::frame(width, height))
+    }
+
+    /// Tiles covering `region` of the image, in their order.
+    pub fn split_region(&self, region: &Tile) -> Vec<Tile> {
+        let size = self.size.max(1);
+        let (width, height) = (region.width, region.height);
+        let (columns, rows) = (width.div_ceil(size), height.div_ceil(size));
+        let mut cells: Vec<(usize, usize)> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect();
+
+        match self.order {
+            TileOrder::Scanline => {}
+            TileOrder::Spiral => {
+                // Twice the offsets from the center keep them integer
+                let center = (columns as i64 - 1, rows as i64 - 1);
+                cells.sort_by(|&a, &b| {
+                    let key = |(column, row): (usize, usize)| {
+                        let dx = 2 * column as i64 - center.0;
+                        let dy = 2 * row as i64 - center.1;
+                        let ring = dx.abs().max(dy.abs());
+                        // Clockwise from the top, as seen on the image
+                        let angle = (dx as f64)
+                            .atan2(-dy as f64)
+                            .rem_euclid(std::f64::consts::TAU);
+                        (ring, angle)
+                    };
+                    let (a, b) = (key(a), key(b));
+                    a.0.cmp(&b.0).then(a.1.total_cmp(&b.1))
+                });
+            }
+            TileOrder::Hilbert => {
+                let side = columns.max(rows).next_power_of_two();
+                cells.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+            }
+        }
+
+        cells
+            .into_iter()
+            .map(|(column, row)| {
+                let (x0, y0) = (column * size, row * size);
+                Tile {
+                    x0: region.x0 + x0,
+                    y0: region.y0 + y0,
+                    width: size.min(width - x0),
+                    height: size.min(height - y0),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Distance along the Hilbert curve filling a `side` by `side` grid, a power
+/// of two, of cell (`x`, `y`).
+pub fn hilbert_index(side: usize, mut x: usize, mut y: usize) -> usize {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = usize::from(x & s != 0);
+        let ry = usize::from(y & s != 0);
+        index += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so that the curve joins the next one
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    exposure::{transform, Matrix3},
+    vec3::{functions::luminance, Color},
+};
+
+/// Conversion of the linear image to the 8 bit sRGB of PNG files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub operator: ToneMapper,
+    /// Exposure compensation in stops, applied before the operator
+    pub exposure: f64,
+}
+
+/// Curve bringing the unbounded radiance of the image into the [0, 1] range
+/// of the display.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ToneMapper {
+    /// Clips every channel at 1.
+    #[default]
+    Clamp,
+    /// Compresses the luminance with `L / (1 + L)`, never reaching white.
+    Reinhard,
+    /// Reinhard curve reaching white at luminance `white`.
+    ExtendedReinhard {
+        #[serde(default = "default_white")]
+        white: f64,
+    },
+    /// Filmic curve fitted to the ACES reference rendering and output
+    /// transforms (Hill), with contrasted and slightly desaturated highlights.
+    Aces,
+    /// Sigmoid in a log encoding of an inset gamut (Sobotka), turning very
+    /// bright colors to white instead of skewing their hue.
+    AgX,
+}
+
+fn default_white() -> f64 {
+    4.0
+}
+
+const ACES_INPUT: Matrix3 = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+const ACES_OUTPUT: Matrix3 = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+const AGX_INSET: Matrix3 = [
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+];
+
+const AGX_OUTSET: Matrix3 = [
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+];
+
+/// Range of the log encoding of AgX, in stops around middle grey.
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+impl ToneMapping {
+    /// Display linear color in [0, 1] for the linear radiance `color`.
+    pub fn apply(&self, color: Color) -> Color {
+        let color = 2f64.powf(self.exposure) * color;
+        let mapped = match self.operator {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapper::ExtendedReinhard { white } => {
+                scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapper::Aces => {
+                let fit = |v: f64| {
+                    (v * (v + 0.0245786) - 0.000090537)
+                        / (v * (0.983729 * v + 0.4329510) + 0.238081)
+                };
+                let c = transform(&ACES_INPUT, color);
+                transform(&ACES_OUTPUT, Color::new(fit(c.x()), fit(c.y()), fit(c.z())))
+            }
+            ToneMapper::AgX => {
+                let curve = |v: f64| {
+                    let v = v.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+                    agx_contrast((v - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV))
+                };
+                let c = transform(&AGX_INSET, color);
+                let c = transform(
+                    &AGX_OUTSET,
+                    Color::new(curve(c.x()), curve(c.y()), curve(c.z())),
+                );
+                // The curve outputs display encoded values, decoded with gamma 2.2
+                let decode = |v: f64| v.max(0.0).powf(2.2);
+                Color::new(decode(c.x()), decode(c.y()), decode(c.z()))
+            }
+        };
+        let clamp = |v: f64| v.clamp(0.0, 1.0);
+        Color::new(clamp(mapped.x()), clamp(mapped.y()), clamp(mapped.z()))
+    }
+
+    /// Tone mapped and sRGB encoded 8 bit pixel of the linear radiance `color`.
+    pub fn encode(&self, color: Color) -> [u8; 3] {
+        let c = self.apply(color);
+        [c.x(), c.y(), c.z()].map(|v| (srgb_oetf(v) * 255.0).round() as u8)
+    }
+}
+
+/// Scales `color` so that its luminance becomes `curve(luminance)`, keeping
+/// its hue and saturation.
+fn scale_luminance(color: Color, curve: impl Fn(f64) -> f64) -> Color {
+    let l = luminance(color);
+    if l <= 0.0 {
+        return Color::default();
+    }
+    curve(l) / l * color
+}
+
+/// Polynomial approximation of the AgX sigmoid over [0, 1].
+fn agx_contrast(x: f64) -> f64 {
+    let (x2, x4) = (x * x, x * x * x * x);
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+        - 0.00232
+}
+
+/// sRGB transfer function, encoding a display linear value in [0, 1].
+pub fn srgb_oetf(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
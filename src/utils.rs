@@ -6,8 +6,6 @@ use std::{
 };
 
 
-use rand::Rng;
-
 use crate::{
     vec3::{Color},
 };
@@ -19,17 +17,6 @@ pub fn deg_to_rad(deg: f64) -> f64 {
     deg * std::f64::consts::PI / 180.0
 }
 
-pub fn random_float() -> f64 {
-    let mut rng = rand::thread_rng();
-
-    rng.gen_range(0.0..1.0)
-}
-
-pub fn random_float_range(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(min..max)
-}
-
 pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     if x < min {
         return min;
@@ -2,9 +2,8 @@
 
 use fast_inv_sqrt::InvSqrt64;
 
-use crate::utils::{random_float, random_float_range};
-
-use super::Vec3;
+use super::{Color, Vec3};
+use palette::Srgb;
 use std::fmt::Display;
 
 impl Vec3 {
@@ -33,42 +32,6 @@ impl Vec3 {
         self[2]
     }
 
-    pub fn random() -> Self {
-        Self::new(random_float(), random_float(), random_float())
-    }
-
-    pub fn random_range(min: f64, max: f64) -> Self {
-        Self::new(
-            random_float_range(min, max),
-            random_float_range(min, max),
-            random_float_range(min, max),
-        )
-    }
-
-    pub fn random_in_unit_sphere() -> Self {
-        loop {
-            let p = Vec3::random_range(-1., 1.);
-            if p.norm_squared() >= 1. {
-                continue;
-            } else {
-                return p;
-            }
-        }
-    }
-
-    pub fn random_unit_vector() -> Self {
-        unit_vec(Vec3::random_in_unit_sphere())
-    }
-
-    pub fn random_in_hemisphere(normal: Vec3) -> Self {
-        let in_unit_sphere = Vec3::random_in_unit_sphere();
-        if dot(in_unit_sphere, normal) > 0.0 {
-            in_unit_sphere
-        } else {
-            -in_unit_sphere
-        }
-    }
-
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
         self[0].abs() < s && self[1].abs() < s && self[2].abs() < s
@@ -96,6 +59,17 @@ pub fn unit_vec(vec: Vec3) -> Vec3 {
     vec * (vec[0] * vec[0] + vec[1] * vec[1] + vec[2] * vec[2]).inv_sqrt64()
 }
 
+/// Two unit vectors completing `n` (assumed unit length) to an orthonormal basis.
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let sign = 1.0_f64.copysign(n.z());
+    let a = -1.0 / (sign + n.z());
+    let b = n.x() * n.y() * a;
+    (
+        Vec3::new(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x()),
+        Vec3::new(b, sign + n.y() * n.y() * a, -n.y()),
+    )
+}
+
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2. * dot(v, n) * n
 }
@@ -108,6 +82,23 @@ pub fn refract(uv: Vec3, n: Vec3, etai_over_eta: f64) -> Vec3 {
     r_out_perp + r_out_parallel
 }
 
+/// Rec. 709 luminance of a linear color.
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+impl From<Srgb> for Color {
+    fn from(color: Srgb) -> Self {
+        Color::new(color.red as f64, color.green as f64, color.blue as f64)
+    }
+}
+
+impl From<Color> for Srgb {
+    fn from(color: Color) -> Self {
+        Srgb::new(color[0] as f32, color[1] as f32, color[2] as f32)
+    }
+}
+
 impl Display for Vec3 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "[{}, {}, {}]", self[0], self[1], self[2])